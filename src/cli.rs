@@ -1,17 +1,105 @@
 #![allow(deprecated)]
 use {
-    crate::models::{
-        assets::{OutputFormat, ReadFrom},
-        get_reader,
+    crate::{
+        config::{layer, Layered, Origin, Source},
+        models::{
+            assets::{Compression, InputFilter, InputFormat, InputPolicy, OutputFormat, ReadFrom},
+            expand_reader,
+            fixed::{load_spec, Column, Columns},
+            get_reader,
+        },
+    },
+    clap::{
+        crate_authors, crate_version, App, Arg, ArgGroup, Error, ErrorKind as ClapError, SubCommand,
     },
-    clap::{crate_authors, crate_version, App, Arg, ArgMatches as Matches, SubCommand},
     csv::Trim,
+    encoding_rs::Encoding,
+    glob::Pattern,
     simplelog::LevelFilter,
-    std::boxed::Box,
+    std::{boxed::Box, time::Duration},
 };
 
-pub fn generate_cli<'a>() -> Matches<'a> {
-    let matches = App::new("aureate")
+// The flag of every option, by (sub)command prefix then option id. Options set
+// through the environment must name one of these, and are reported by them.
+// Must be kept in step with build_cli, which the tests check
+pub const OPTIONS: [(&str, &[(&str, &str)]); 5] = [
+    (
+        "",
+        &[
+            ("verbosity", "-v"),
+            ("config", "--config"),
+            ("profile", "--profile"),
+            ("quiet", "--quiet"),
+            ("append", "--append"),
+            ("lock", "--lock"),
+            ("mmap", "--mmap"),
+            ("follow", "--follow"),
+            ("format", "--format"),
+            ("input", "--input"),
+            ("input_policy", "--on-input-error"),
+            ("recursive", "--recursive"),
+            ("include", "--include"),
+            ("exclude", "--exclude"),
+            ("output", "--output"),
+            ("compress", "--compress"),
+            ("compress_level", "--compress-level"),
+            ("jobs", "--jobs"),
+            ("input_jobs", "--input-jobs"),
+            ("unordered", "--unordered"),
+            ("batch_size", "--batch-size"),
+            ("batch_bytes", "--batch-bytes"),
+            ("channel_depth", "--channel-depth"),
+            ("stats_json", "--stats-json"),
+            ("no_progress", "--no-progress"),
+            ("progress_events", "--progress-events"),
+            ("progress_interval", "--progress-interval"),
+            ("materialize", "--materialize"),
+            ("encoding", "--encoding"),
+            ("sniff", "--sniff"),
+            ("lossy", "--lossy"),
+        ],
+    ),
+    (
+        "csv.",
+        &[
+            ("dialect_csv", "--dialect"),
+            ("delimiter_csv", "--delimiter"),
+            ("delimiter_str_csv", "--delimiter-str"),
+            ("flexible_csv", "--flexible"),
+            ("trim_settings_csv", "--trim"),
+            ("comment_csv", "--comment"),
+            ("quote_settings_csv", "--disable-quotes"),
+            ("quote_csv", "--quote"),
+            ("escape_csv", "--escape"),
+        ],
+    ),
+    (
+        "fixed.",
+        &[
+            ("columns_fixed", "--columns"),
+            ("spec_fixed", "--spec"),
+            ("auto_fixed", "--auto"),
+            ("header_fixed", "--header"),
+            ("no_trim_fixed", "--no-trim"),
+        ],
+    ),
+    (
+        "whitespace.",
+        &[
+            ("max_split_whitespace", "--max-split"),
+            ("no_header_whitespace", "--no-header"),
+        ],
+    ),
+    ("tsv.", &[("no_header_tsv", "--no-header")]),
+];
+
+// Parses the command line, layered over any config file
+pub fn generate_cli() -> Layered<'static> {
+    layer(build_cli().get_matches(), build_cli, &OPTIONS)
+}
+
+fn build_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("aureate")
         .about("Utility for converting CSV to JSON/YAML")
         .author(crate_authors!("\n"))
         .version(crate_version!())
@@ -23,6 +111,21 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                 .takes_value(false)
                 .help("Sets level of debug output"),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Load options from a config file")
+                .long_help("Load options from a config file (.toml or .yaml). If unset, aureate.toml, aureate.yaml or aureate.yml is looked for in the working directory, then the home directory. Options given on the command line take precedence over the selected profile, which takes precedence over the rest of the file"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("NAME")
+                .takes_value(true)
+                .help("Select a named profile from the config file"),
+        )
         .arg(Arg::with_name("quiet")
                 .short("q")
                 .long("quiet")
@@ -34,14 +137,32 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                 .long("append")
                 .takes_value(false)
                 .help("Append to output file, instead of overwriting")
-                .long_help("Append to output file, instead of overwriting... has no effect if writing to stdout")
+                .long_help("Append to output file, instead of overwriting... has no effect if writing to stdout. Json output continues an existing array, Yaml an existing sequence, and ndjson adds lines. Compressed output cannot be appended to")
+        )
+        .arg(Arg::with_name("lock")
+                .long("lock")
+                .takes_value(false)
+                .help("Take an advisory lock on the output file while writing")
+                .long_help("Take an advisory lock ('<FILE>.lock') on the output file while writing, waiting for any other aureate process holding it... has no effect if writing to stdout")
+        )
+        .arg(Arg::with_name("mmap")
+                .long("mmap")
+                .takes_value(false)
+                .help("Memory map input files instead of reading them")
+                .long_help("Memory map input files instead of reading them, so the parser is filled from the page cache without a read system call per buffer. Stdin, pipes, special files and followed files are always read normally")
+        )
+        .arg(Arg::with_name("follow")
+                .long("follow")
+                .takes_value(false)
+                .help("Keep reading a file as it grows, like 'tail -F'")
+                .long_help("Keep reading a single input file as it grows, like 'tail -F', writing each row as soon as it is complete. Truncated and replaced (rotated) files are followed. Requires ndjson or tsv output, which is written in place and flushed after every row rather than staged")
         )
         .arg(
             Arg::with_name("format")
                 .short("f")
                 .long("format")
                 .takes_value(true)
-                .possible_values(&["prettyj", "json", "yaml"])
+                .possible_values(&["prettyj", "json", "ndjson", "yaml", "tsv"])
                 .default_value("prettyj")
                 .help("Set output data format"),
         )
@@ -53,7 +174,46 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                 .takes_value(true)
                 .multiple(true)
                 .require_delimiter(true)
-                .help("Input file path(s) separated by commas, with a '-' representing stdin"),
+                .help("Input file path(s) separated by commas, with a '-' representing stdin")
+                .long_help("Input file path(s) separated by commas, with a '-' representing stdin. Directories and glob patterns (e.g: 'exports/**/*.csv') are expanded into the files they contain, in sorted order"),
+        )
+        .arg(
+            Arg::with_name("input_policy")
+                .long("on-input-error")
+                .value_name("POLICY")
+                .takes_value(true)
+                .possible_values(&["fail", "skip", "stdin"])
+                .default_value("fail")
+                .help("Set what happens when an input cannot be opened")
+                .long_help("Set what happens when an input cannot be opened: 'fail' exits with an error, 'skip' skips it with a warning and 'stdin' reads from stdin in its place"),
+        )
+        .arg(Arg::with_name("recursive")
+                .short("r")
+                .long("recursive")
+                .takes_value(false)
+                .help("Recursively expand directory inputs")
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .multiple(true)
+                .require_delimiter(true)
+                .validator(|s: String| Pattern::new(&s).map(|_| ()).map_err(|e| e.to_string()))
+                .help("Only read files matching these glob pattern(s), when expanding directory or glob inputs")
+                .long_help("Only read files whose names match these glob pattern(s), when expanding directory or glob inputs, e.g: '*.csv'"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .multiple(true)
+                .require_delimiter(true)
+                .validator(|s: String| Pattern::new(&s).map(|_| ()).map_err(|e| e.to_string()))
+                .help("Skip files matching these glob pattern(s), when expanding directory or glob inputs")
+                .long_help("Skip files whose names match these glob pattern(s), when expanding directory or glob inputs. Takes precedence over --include"),
         )
         .arg(
             Arg::with_name("output")
@@ -63,11 +223,179 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                 .takes_value(true)
                 .help("Specify an output file path, defaults to stdout"),
         )
+        .arg(
+            Arg::with_name("compress")
+                .long("compress")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(&["gzip", "zstd"])
+                .help("Compress output")
+                .long_help("Compress output. If unset, compression is inferred from a '.gz' or '.zst' output file extension"),
+        )
+        .arg(
+            Arg::with_name("compress_level")
+                .long("compress-level")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .validator(|s: String| match s.parse::<u32>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(format!("Compression level must be a positive integer")),
+                })
+                .help("Set output compression level")
+                .long_help("Set output compression level, gzip: [0-9] (default: 6), zstd: [1-22] (default: 3)"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|s: String| match s.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(format!("Jobs must be a positive integer")),
+                })
+                .help("Set the number of threads building and serializing records")
+                .long_help("Set the number of threads building and serializing records. With more than one, records are serialized in chunks and the output joined in their original order by the writer"),
+        )
+        .arg(
+            Arg::with_name("input_jobs")
+                .long("input-jobs")
+                .value_name("N")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|s: String| match s.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(format!("Input jobs must be a positive integer")),
+                })
+                .help("Set the number of inputs read and parsed concurrently")
+                .long_help("Set the number of inputs read and parsed concurrently. Output is still written in input order, unless --unordered is set"),
+        )
+        .arg(Arg::with_name("unordered")
+                .long("unordered")
+                .takes_value(false)
+                .help("Write concurrently read inputs in completion order, instead of input order")
+                .long_help("Write concurrently read inputs in the order they become ready (finished, or with a full buffer), instead of input order. Has no effect unless --input-jobs is greater than 1")
+        )
+        .arg(
+            Arg::with_name("batch_size")
+                .long("batch-size")
+                .value_name("ROWS")
+                .takes_value(true)
+                .default_value("1024")
+                .validator(|s: String| match s.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(format!("Batch size must be a positive integer")),
+                })
+                .help("Set the maximum number of records passed between threads at a time"),
+        )
+        .arg(
+            Arg::with_name("batch_bytes")
+                .long("batch-bytes")
+                .value_name("BYTES")
+                .takes_value(true)
+                .default_value("1048576")
+                .validator(|s: String| match s.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(format!("Batch bytes must be a positive integer")),
+                })
+                .help("Set the maximum size of a batch of records, in bytes of field data")
+                .long_help("Set the maximum size of a batch of records, in bytes of field data. A batch is passed on once it reaches either this or --batch-size"),
+        )
+        .arg(
+            Arg::with_name("channel_depth")
+                .long("channel-depth")
+                .value_name("BATCHES")
+                .takes_value(true)
+                .default_value("4")
+                .validator(|s: String| match s.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(format!("Channel depth must be a positive integer")),
+                })
+                .help("Set the number of batches buffered between threads")
+                .long_help("Set the number of batches buffered between threads, per input. Together with the batch limits, this bounds memory use"),
+        )
+        .arg(
+            Arg::with_name("stats_json")
+                .long("stats-json")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Write run statistics to a file as JSON")
+                .long_help("Write run statistics (rows read, rejected and written, bytes read, elapsed time and throughput, overall and per input) to a file as JSON, once all output is written"),
+        )
+        .arg(Arg::with_name("no_progress")
+                .long("no-progress")
+                .takes_value(false)
+                .help("Disable the progress indicator")
+                .long_help("Disable the progress indicator. It is only shown when stderr is a terminal, and never with --quiet")
+        )
+        .arg(Arg::with_name("progress_events")
+                .long("progress-events")
+                .takes_value(false)
+                .help("Periodically write progress as JSON lines to stderr")
+                .long_help("Periodically write progress (rows, bytes read against total input size, throughput and ETA) as JSON lines to stderr, for wrapper scripts. Unaffected by --quiet and --no-progress")
+        )
+        .arg(
+            Arg::with_name("progress_interval")
+                .long("progress-interval")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .default_value("1")
+                // Bounded, as Duration::from_secs_f64 panics on overflow
+                .validator(|s: String| match s.parse::<f64>() {
+                    Ok(n) if n > 0.0 && n <= std::u32::MAX as f64 => Ok(()),
+                    _ => Err(format!(
+                        "Progress interval must be a positive number of seconds, at most {}",
+                        std::u32::MAX
+                    )),
+                })
+                .help("Set how often progress is reported"),
+        )
+        .arg(Arg::with_name("materialize")
+                .long("materialize")
+                .takes_value(false)
+                .hidden(true)
+                .help("Build an intermediate JSON/YAML value for each record before writing it")
+                .long_help("Build an intermediate JSON/YAML value for each record before writing it, instead of serializing records directly. Slower, kept for comparison")
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .long("encoding")
+                .value_name("LABEL")
+                .takes_value(true)
+                .validator(|s: String| match Encoding::for_label(s.as_bytes()) {
+                    Some(_) => Ok(()),
+                    None => Err(format!("Unknown encoding label: {}", s)),
+                })
+                .help("Set the character encoding of input(s)")
+                .long_help("Set the character encoding of input(s), e.g: latin1, windows-1252, utf-16le. Input is transcoded to UTF-8 before parsing. If unset, the encoding is detected from a byte order mark, falling back to UTF-8"),
+        )
+        .arg(Arg::with_name("sniff")
+                .long("sniff")
+                .takes_value(false)
+                .help("Guess the CSV dialect of each input")
+                .long_help("Guess the delimiter, quote character, escape style, presence of a header row and whether rows are ragged from the first few KB of each input. Options set explicitly (e.g: with 'csv --delimiter') are kept as given. Inputs without a header row get generated headers")
+        )
+        .arg(Arg::with_name("lossy")
+                .long("lossy")
+                .takes_value(false)
+                .help("Replace invalid UTF-8 sequences instead of skipping the record")
+                .long_help("Replace invalid UTF-8 sequences with the replacement character (U+FFFD) instead of skipping the record they appear in")
+        )
         .subcommand(
             SubCommand::with_name("csv")
                 .about("Settings related to fine-tuning the CSV reader")
                 .alias(" ")
-                .after_help("NOTE: options which take <CHAR> accept one and only one character... everything else will be dropped!")
+                .after_help("NOTE: options which take <CHAR> accept a single ASCII character, an escape ('\\t', '\\\\', '\\x1f') or a name: comma, semicolon, colon, pipe, tab, space, backslash, double-quote, single-quote, unit-separator (us), record-separator (rs)")
+                .arg(
+                    Arg::with_name("dialect_csv")
+                        .long("dialect")
+                        .takes_value(true)
+                        .possible_values(&["excel", "excel-tab", "rfc4180", "unix", "semicolon"])
+                        .value_name("PRESET")
+                        .help("Use a preset CSV dialect")
+                        .long_help("Use a preset CSV dialect. excel and rfc4180: ',' delimited, '\"' quoted with doubled quotes; excel-tab: as excel, but tab delimited; unix: as excel, but also accepting backslash escaped quotes; semicolon: as excel, but ';' delimited. Options given alongside override the preset")
+                )
                 .arg(
                     Arg::with_name("delimiter_csv")
                         .short("s")
@@ -75,8 +403,21 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                         .takes_value(true)
                         .default_value(",")
                         .value_name("CHAR")
+                        .validator(|s: String| parse_char(&s).map(|_| ()))
                         .help("Specify your CSV delimiter"),
                 )
+                .arg(
+                    Arg::with_name("delimiter_str_csv")
+                        .long("delimiter-str")
+                        .takes_value(true)
+                        .value_name("STRING")
+                        .validator(|s: String| match s.is_empty() {
+                            true => Err(format!("The delimiter cannot be empty")),
+                            false => Ok(()),
+                        })
+                        .help("Specify a multi-character delimiter, e.g: '||'")
+                        .long_help("Specify a delimiter of one or more characters, e.g: '||' or '~|~'. Takes precedence over --delimiter. Quoting, escapes, comments, trimming and --flexible are honored as usual")
+                )
                 .arg(
                     Arg::with_name("flexible_csv")
                         .long("flexible")
@@ -105,6 +446,7 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                         .long("comment")
                         .takes_value(true)
                         .value_name("CHAR")
+                        .validator(|s: String| parse_char(&s).map(|_| ()))
                         .help("Specify your CSV comment character")
                 )
                 .arg(
@@ -122,6 +464,7 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                         .long("quote")
                         .default_value("\"")
                         .value_name("CHAR")
+                        .validator(|s: String| parse_char(&s).map(|_| ()))
                         .help("Specify your CSV quote character")
                 )
                 .arg(
@@ -130,12 +473,93 @@ pub fn generate_cli<'a>() -> Matches<'a> {
                         .long("escape")
                         .takes_value(true)
                         .value_name("CHAR")
+                        .validator(|s: String| parse_char(&s).map(|_| ()))
                         .help("Specify your CSV escape character")
                 )
         )
-        .get_matches();
-
-    matches
+        .subcommand(
+            SubCommand::with_name("fixed")
+                .about("Read fixed-width input, split into columns by position")
+                .alias("fixed-width")
+                .after_help("NOTE: column positions are counted in characters, the first being 1")
+                .group(
+                    ArgGroup::with_name("layout_fixed")
+                        .args(&["columns_fixed", "spec_fixed", "auto_fixed"])
+                        .required(true)
+                )
+                .arg(
+                    Arg::with_name("columns_fixed")
+                        .long("columns")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .value_name("NAME:START:WIDTH")
+                        .validator(|s: String| Column::parse(&s).map(|_| ()))
+                        .help("Specify the columns, e.g: 'id:1:8,name:9:20'")
+                )
+                .arg(
+                    Arg::with_name("spec_fixed")
+                        .long("spec")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Read the columns from a file")
+                        .long_help("Read the columns from a file, one NAME:START:WIDTH per line. Blank lines and lines starting with '#' are ignored")
+                )
+                .arg(
+                    Arg::with_name("auto_fixed")
+                        .long("auto")
+                        .takes_value(false)
+                        .help("Detect the columns from the header line")
+                        .long_help("Detect the columns from the header line: each column starts where a header name does, and runs until the next one")
+                )
+                .arg(
+                    Arg::with_name("header_fixed")
+                        .long("header")
+                        .takes_value(false)
+                        .help("Skip the first line, as a header")
+                        .long_help("Treat the first line as a header and skip it, column names are always taken from the columns given. Implied by --auto")
+                )
+                .arg(
+                    Arg::with_name("no_trim_fixed")
+                        .long("no-trim")
+                        .takes_value(false)
+                        .help("Keep the padding around each field")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("whitespace")
+                .about("Read column aligned input, split on runs of whitespace")
+                .alias("aligned")
+                .arg(
+                    Arg::with_name("max_split_whitespace")
+                        .long("max-split")
+                        .takes_value(true)
+                        .value_name("N")
+                        .validator(|s: String| match s.parse::<usize>() {
+                            Ok(_) => Ok(()),
+                            Err(_) => Err(format!("Must be a whole number")),
+                        })
+                        .help("Split each line at most N times")
+                        .long_help("Split each line at most N times, the last field keeps the rest of the line and any spaces in it, e.g: --max-split 5 for the 'Mounted on' column of df")
+                )
+                .arg(
+                    Arg::with_name("no_header_whitespace")
+                        .long("no-header")
+                        .takes_value(false)
+                        .help("The first line is not a header, generate headers instead")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("tsv")
+                .about("Read TSV, with backslash escaped tabs and line breaks instead of quoting")
+                .after_help("NOTE: fields are unescaped from \\t, \\n, \\r and \\\\, any other backslash is kept as is")
+                .arg(
+                    Arg::with_name("no_header_tsv")
+                        .long("no-header")
+                        .takes_value(false)
+                        .help("The first line is not a header, generate headers instead")
+                )
+        )
 }
 
 pub struct ProgramArgs {
@@ -143,8 +567,32 @@ pub struct ProgramArgs {
     //_store: Matches<'a>,
     debug_level: LevelFilter,
     output_type: OutputFormat,
-    reader: Vec<Option<ReadFrom>>,
+    reader: Vec<ReadFrom>,
+    input_format: InputFormat,
+    input_policy: InputPolicy,
     writer: (Option<String>, bool),
+    lock: bool,
+    mmap: bool,
+    follow: bool,
+    compression: Option<(Compression, Option<u32>)>,
+    encoding: Option<&'static Encoding>,
+    lossy: bool,
+    sniff: bool,
+    jobs: usize,
+    input_jobs: usize,
+    unordered: bool,
+    materialize: bool,
+    batch_size: usize,
+    batch_bytes: usize,
+    channel_depth: usize,
+    stats_json: Option<String>,
+    progress: bool,
+    progress_events: bool,
+    progress_interval: Duration,
+    // Where each option's value came from
+    sources: Vec<(String, Source)>,
+    // AUREATE_* environment variables which are not options
+    ignored_env: Vec<String>,
     // CSV
     flexible_csv: CSVOption,
     delimiter_csv: CSVOption,
@@ -153,10 +601,11 @@ pub struct ProgramArgs {
     quote_csv: CSVOption,
     trim_settings_csv: CSVOption,
     quote_settings_csv: CSVOption,
+    delimiter_str_csv: Option<String>,
 }
 
 impl<'a> ProgramArgs {
-    pub fn init(store: Matches<'a>) -> Self {
+    pub fn init(store: Layered<'a>) -> Self {
         let debug_level = match (store.occurrences_of("verbosity"), store.is_present("quiet")) {
             (_, true) => LevelFilter::Off,
             (0, false) => LevelFilter::Warn,
@@ -168,10 +617,17 @@ impl<'a> ProgramArgs {
         let output_type = match store.value_of("format") {
             Some("prettyj") => OutputFormat::JsonPretty,
             Some("json") => OutputFormat::Json,
+            Some("ndjson") => OutputFormat::NdJson,
             Some("yaml") => OutputFormat::Yaml,
+            Some("tsv") => OutputFormat::Tsv,
             _ => unreachable!(),
         };
 
+        let filter = InputFilter {
+            recursive: store.is_present("recursive"),
+            include: patterns_of(&store, "include"),
+            exclude: patterns_of(&store, "exclude"),
+        };
         let reader = match store.values_of("input") {
             Some(inputs) => inputs
                 .scan(false, |acc, item| match item {
@@ -183,20 +639,160 @@ impl<'a> ProgramArgs {
                     _ => Some((false, item)),
                 })
                 .filter(|(dupe, _)| !dupe)
-                .map(|(_, s)| get_reader(Some(s)))
-                .collect::<Vec<Option<ReadFrom>>>(),
+                .flat_map(|(_, s)| expand_reader(s, &filter))
+                .collect::<Vec<ReadFrom>>(),
             None => {
-                let mut vec: Vec<Option<ReadFrom>> = Vec::new();
+                let mut vec: Vec<ReadFrom> = Vec::new();
                 let i = get_reader(None);
                 vec.push(i);
                 vec
             }
         };
+        let input_format = match store.subcommand_name() {
+            Some("fixed") => {
+                let fixed = store.subcommand_matches("fixed").unwrap();
+                let columns = match (
+                    fixed.values_of("columns_fixed"),
+                    fixed.value_of("spec_fixed"),
+                ) {
+                    (Some(columns), _) => {
+                        Columns::Given(columns.map(|c| Column::parse(c).unwrap()).collect())
+                    }
+                    (None, Some(path)) => match load_spec(path) {
+                        Ok(columns) => Columns::Given(columns),
+                        Err(e) => {
+                            Error::with_description(&e.to_string(), ClapError::InvalidValue).exit()
+                        }
+                    },
+                    (None, None) => Columns::Detect,
+                };
+                InputFormat::FixedWidth {
+                    columns,
+                    header: fixed.is_present("header_fixed"),
+                    trim: !fixed.is_present("no_trim_fixed"),
+                }
+            }
+            Some("whitespace") => {
+                let whitespace = store.subcommand_matches("whitespace").unwrap();
+                InputFormat::Whitespace {
+                    max_split: whitespace
+                        .value_of("max_split_whitespace")
+                        .map(|n| n.parse::<usize>().unwrap()),
+                    header: !whitespace.is_present("no_header_whitespace"),
+                }
+            }
+            Some("tsv") => InputFormat::Tsv {
+                header: !store
+                    .subcommand_matches("tsv")
+                    .unwrap()
+                    .is_present("no_header_tsv"),
+            },
+            _ => InputFormat::Csv,
+        };
+        let input_policy = match store.value_of("input_policy") {
+            Some("fail") => InputPolicy::Fail,
+            Some("skip") => InputPolicy::Skip,
+            Some("stdin") => InputPolicy::Stdin,
+            _ => unreachable!(),
+        };
         let writer = match (store.value_of("output"), store.is_present("append")) {
             (Some(s), false) => (Some(s.to_string()), false),
             (Some(s), true) => (Some(s.to_string()), true),
             (None, _) => (None, false),
         };
+        let lock = store.is_present("lock");
+        let mmap = store.is_present("mmap");
+        let compression = match (store.value_of("compress"), &writer) {
+            (Some("gzip"), _) => Some(Compression::Gzip),
+            (Some("zstd"), _) => Some(Compression::Zstd),
+            (Some(_), _) => unreachable!(),
+            (None, (Some(file_name), _)) if file_name.ends_with(".gz") => Some(Compression::Gzip),
+            (None, (Some(file_name), _)) if file_name.ends_with(".zst") => Some(Compression::Zstd),
+            (None, _) => None,
+        }
+        .map(|format| {
+            let level = store
+                .value_of("compress_level")
+                .map(|l| l.parse::<u32>().unwrap());
+            match (format, level) {
+                (Compression::Gzip, Some(l)) if l > 9 => Error::with_description(
+                    "Gzip compression level must be between 0 and 9",
+                    ClapError::InvalidValue,
+                )
+                .exit(),
+                (Compression::Zstd, Some(l)) if l < 1 || l > 22 => Error::with_description(
+                    "Zstd compression level must be between 1 and 22",
+                    ClapError::InvalidValue,
+                )
+                .exit(),
+                _ => (format, level),
+            }
+        });
+        let follow = store.is_present("follow");
+        if follow {
+            let invalid = |reason: &str| -> ! {
+                Error::with_description(
+                    &format!("--follow {}", reason),
+                    ClapError::ArgumentConflict,
+                )
+                .exit()
+            };
+            match reader.as_slice() {
+                [ReadFrom::File(_)] => (),
+                _ => invalid("requires a single input file"),
+            }
+            match output_type {
+                OutputFormat::NdJson | OutputFormat::Tsv => (),
+                _ => invalid("requires line oriented output, either ndjson or tsv"),
+            }
+            if compression.is_some() {
+                invalid("cannot be combined with compressed output")
+            }
+        }
+        let encoding = store
+            .value_of("encoding")
+            .and_then(|label| Encoding::for_label(label.as_bytes()));
+        let lossy = store.is_present("lossy");
+        let sniff = store.is_present("sniff");
+        let jobs = store.value_of("jobs").unwrap().parse::<usize>().unwrap();
+        let input_jobs = store
+            .value_of("input_jobs")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let unordered = store.is_present("unordered");
+        let materialize = store.is_present("materialize");
+        let stats_json = store.value_of("stats_json").map(|s| s.to_string());
+        let progress = !store.is_present("no_progress")
+            && debug_level != LevelFilter::Off
+            && atty::is(atty::Stream::Stderr);
+        let progress_events = store.is_present("progress_events");
+        let progress_interval = Duration::from_secs_f64(
+            store
+                .value_of("progress_interval")
+                .unwrap()
+                .parse::<f64>()
+                .unwrap(),
+        );
+        // Followed rows are sent on as soon as they are read
+        let batch_size = match follow {
+            true => 1,
+            false => store
+                .value_of("batch_size")
+                .unwrap()
+                .parse::<usize>()
+                .unwrap(),
+        };
+        let batch_bytes = store
+            .value_of("batch_bytes")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let channel_depth = store
+            .value_of("channel_depth")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
 
         // CSV reader options
         /* ---------------------------------------- */
@@ -208,33 +804,39 @@ impl<'a> ProgramArgs {
         let quote_csv: CSVOption;
         let trim_settings_csv: CSVOption;
         let quote_settings_csv: CSVOption;
+        let delimiter_str_csv: Option<String>;
 
         match store.subcommand_matches("csv") {
             Some(csv) => {
                 flexible_csv = CSVOption::Flexible(csv.is_present("flexible_csv"));
 
-                delimiter_csv = CSVOption::DelimiterChar(match csv.value_of("delimiter_csv") {
-                    Some("\\t") => "\t".bytes().nth(0).unwrap(),
-                    Some(s) => s.bytes().nth(0).unwrap(),
-                    _ => unreachable!(),
-                });
+                // Characters are validated by the cli, a preset fills in any not given
+                let preset = csv.value_of("dialect_csv").map(dialect_preset);
+                let given = |name: &str| match csv.occurrences_of(name) {
+                    0 => None,
+                    _ => csv.value_of(name).map(|s| parse_char(s).unwrap()),
+                };
 
-                escape_csv = CSVOption::EscapeChar(match csv.value_of("escape_csv") {
-                    Some("\\t") => Some("\t".bytes().nth(0).unwrap()),
-                    Some(s) => Some(s.bytes().nth(0).unwrap()),
-                    None => None,
+                delimiter_csv = CSVOption::DelimiterChar(match (given("delimiter_csv"), preset) {
+                    (Some(c), _) => c,
+                    (None, Some((delimiter, _, _))) => delimiter,
+                    (None, None) => b',',
                 });
 
-                comment_csv = CSVOption::CommentChar(match csv.value_of("comment_csv") {
-                    Some("\\t") => Some("\t".bytes().nth(0).unwrap()),
-                    Some(s) => Some(s.bytes().nth(0).unwrap()),
-                    None => None,
+                escape_csv = CSVOption::EscapeChar(match (given("escape_csv"), preset) {
+                    (Some(c), _) => Some(c),
+                    (None, Some((_, _, escape))) => escape,
+                    (None, None) => None,
                 });
 
-                quote_csv = CSVOption::QuoteChar(match csv.value_of("quote_csv") {
-                    Some("\\t") => "\t".bytes().nth(0).unwrap(),
-                    Some(s) => s.bytes().nth(0).unwrap(),
-                    _ => unreachable!(),
+                comment_csv = CSVOption::CommentChar(
+                    csv.value_of("comment_csv").map(|s| parse_char(s).unwrap()),
+                );
+
+                quote_csv = CSVOption::QuoteChar(match (given("quote_csv"), preset) {
+                    (Some(c), _) => c,
+                    (None, Some((_, quote, _))) => quote,
+                    (None, None) => b'"',
                 });
 
                 trim_settings_csv =
@@ -258,6 +860,8 @@ impl<'a> ProgramArgs {
                         },
                         None => (true, true),
                     });
+
+                delimiter_str_csv = csv.value_of("delimiter_str_csv").map(|s| s.to_string());
             }
             None => {
                 flexible_csv = CSVOption::Flexible(false);
@@ -267,17 +871,43 @@ impl<'a> ProgramArgs {
                 quote_csv = CSVOption::QuoteChar(b'"');
                 trim_settings_csv = CSVOption::TrimSettings(Trim::None);
                 quote_settings_csv = CSVOption::QuoteSettings((true, true));
+                delimiter_str_csv = None;
             }
         }
         /* ---------------------------------------- */
 
+        let sources = store.sources();
+        let ignored_env = store.ignored();
+
         Self {
             //Program Options
             //_store: store,
             debug_level,
             output_type,
             reader,
+            input_format,
+            input_policy,
             writer,
+            lock,
+            mmap,
+            follow,
+            compression,
+            encoding,
+            lossy,
+            sniff,
+            jobs,
+            input_jobs,
+            unordered,
+            materialize,
+            batch_size,
+            batch_bytes,
+            channel_depth,
+            stats_json,
+            progress,
+            progress_events,
+            progress_interval,
+            sources,
+            ignored_env,
 
             //CSV Options
             flexible_csv,
@@ -287,6 +917,7 @@ impl<'a> ProgramArgs {
             quote_csv,
             trim_settings_csv,
             quote_settings_csv,
+            delimiter_str_csv,
         }
     }
 
@@ -298,14 +929,112 @@ impl<'a> ProgramArgs {
         self.output_type
     }
 
-    pub fn reader_list(&self) -> &Vec<Option<ReadFrom>> {
+    pub fn reader_list(&self) -> &Vec<ReadFrom> {
         &self.reader
     }
 
+    pub fn input_format(&self) -> &InputFormat {
+        &self.input_format
+    }
+
+    pub fn input_policy(&self) -> InputPolicy {
+        self.input_policy
+    }
+
     pub fn writer(&self) -> &(Option<String>, bool) {
         &self.writer
     }
 
+    pub fn lock(&self) -> bool {
+        self.lock
+    }
+
+    pub fn mmap(&self) -> bool {
+        self.mmap
+    }
+
+    pub fn follow(&self) -> bool {
+        self.follow
+    }
+
+    pub fn compression(&self) -> Option<(Compression, Option<u32>)> {
+        self.compression
+    }
+
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        self.encoding
+    }
+
+    pub fn lossy(&self) -> bool {
+        self.lossy
+    }
+
+    pub fn sniff(&self) -> bool {
+        self.sniff
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    pub fn input_jobs(&self) -> usize {
+        self.input_jobs
+    }
+
+    pub fn unordered(&self) -> bool {
+        self.unordered
+    }
+
+    pub fn materialize(&self) -> bool {
+        self.materialize
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn batch_bytes(&self) -> usize {
+        self.batch_bytes
+    }
+
+    pub fn channel_depth(&self) -> usize {
+        self.channel_depth
+    }
+
+    pub fn stats_json(&self) -> Option<&str> {
+        self.stats_json.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn progress(&self) -> bool {
+        self.progress
+    }
+
+    pub fn progress_events(&self) -> bool {
+        self.progress_events
+    }
+
+    pub fn progress_interval(&self) -> Duration {
+        self.progress_interval
+    }
+
+    pub fn sources(&self) -> &Vec<(String, Source)> {
+        &self.sources
+    }
+
+    pub fn ignored_env(&self) -> &Vec<String> {
+        &self.ignored_env
+    }
+
+    // Whether an option was set by the user, rather than left at its default
+    pub fn is_explicit(&self, name: &str) -> bool {
+        self.sources
+            .iter()
+            .any(|(option, source)| match (option == name, &source.origin) {
+                (true, Origin::Default) => false,
+                (matches, _) => matches,
+            })
+    }
+
     // CSV.ReaderBuilder related methods
 
     pub fn delimiter(&self) -> u8 {
@@ -335,6 +1064,77 @@ impl<'a> ProgramArgs {
     pub fn quote_settings(&self) -> (bool, bool) {
         self.quote_settings_csv.into()
     }
+
+    pub fn delimiter_str(&self) -> Option<&str> {
+        self.delimiter_str_csv.as_ref().map(|s| s.as_str())
+    }
+}
+
+// Collects the (pre-validated) glob patterns of a given argument
+fn patterns_of(store: &Layered, name: &str) -> Vec<Pattern> {
+    store
+        .values_of(name)
+        .map(|patterns| patterns.filter_map(|p| Pattern::new(p).ok()).collect())
+        .unwrap_or_default()
+}
+
+// Parses a <CHAR> option: a single ASCII character, an escape or a name.
+// Anything which is not exactly one byte is an error, rather than being truncated
+fn parse_char(s: &str) -> Result<u8, String> {
+    let named = match s {
+        "comma" => Some(b','),
+        "semicolon" => Some(b';'),
+        "colon" => Some(b':'),
+        "pipe" => Some(b'|'),
+        "tab" | "\\t" => Some(b'\t'),
+        "space" => Some(b' '),
+        "backslash" | "\\\\" => Some(b'\\'),
+        "double-quote" => Some(b'"'),
+        "single-quote" => Some(b'\''),
+        "unit-separator" | "us" => Some(0x1f),
+        "record-separator" | "rs" => Some(0x1e),
+        _ => None,
+    };
+    if let Some(c) = named {
+        return Ok(c);
+    }
+
+    if s.starts_with("\\x") {
+        // from_str_radix would also take a sign, e.g: '\x+f'
+        let digits = s[2..].bytes().all(|b| b.is_ascii_hexdigit());
+        return match u8::from_str_radix(&s[2..], 16) {
+            Ok(c) if digits && s.len() == 4 && c.is_ascii() => Ok(c),
+            _ => Err(format!(
+                "'{}' is not a valid escape, expected '\\x00' to '\\x7f'",
+                s
+            )),
+        };
+    }
+
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() => Ok(c as u8),
+        (Some(c), None) => Err(format!(
+            "'{}' is {} bytes long, only single byte (ASCII) characters are supported",
+            c,
+            c.len_utf8()
+        )),
+        _ => Err(format!(
+            "'{}' is not a single character, an escape or a known name",
+            s
+        )),
+    }
+}
+
+// The (delimiter, quote, escape) of a --dialect preset
+fn dialect_preset(name: &str) -> (u8, u8, Option<u8>) {
+    match name {
+        "excel" | "rfc4180" => (b',', b'"', None),
+        "excel-tab" => (b'\t', b'"', None),
+        "unix" => (b',', b'"', Some(b'\\')),
+        "semicolon" => (b';', b'"', None),
+        _ => unreachable!(),
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -394,3 +1194,103 @@ impl From<CSVOption> for Trim {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_char_accepts() {
+        let cases = [
+            (",", b','),
+            ("comma", b','),
+            ("semicolon", b';'),
+            ("colon", b':'),
+            ("pipe", b'|'),
+            ("tab", b'\t'),
+            ("\\t", b'\t'),
+            ("space", b' '),
+            ("backslash", b'\\'),
+            ("\\\\", b'\\'),
+            ("double-quote", b'"'),
+            ("single-quote", b'\''),
+            ("unit-separator", 0x1f),
+            ("us", 0x1f),
+            ("record-separator", 0x1e),
+            ("rs", 0x1e),
+            ("\\x00", 0x00),
+            ("\\x1F", 0x1f),
+            ("\\x7f", 0x7f),
+            ("\\", b'\\'),
+        ];
+
+        for (s, expected) in cases.iter() {
+            assert_eq!(parse_char(s), Ok(*expected), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_char_rejects() {
+        // Not ASCII, so not a single byte
+        let multi_byte = ["é", "€", "\\x80", "\\xff"];
+        // Not exactly one character
+        let unknown = [
+            "", ";;", "Comma", "\\x7", "\\x7f0", "\\xzz", "\\x+f", "\\x-1",
+        ];
+
+        for s in multi_byte.iter().chain(unknown.iter()) {
+            assert!(
+                parse_char(s).is_err(),
+                "{:?} parsed as {:?}",
+                s,
+                parse_char(s)
+            );
+        }
+    }
+
+    // Parses a (sub)command's flags out of its help, as shown to users
+    fn help_flags(args: &[&str]) -> Vec<String> {
+        let help = match build_cli().get_matches_from_safe(args) {
+            Err(e) if e.kind == ClapError::HelpDisplayed => e.message,
+            _ => panic!("{:?} did not show help", args),
+        };
+        // Lines listing an option start with its flags, e.g: '-f, --format <FORMAT>'
+        help.lines()
+            .map(|line| line.trim_start())
+            .filter(|line| line.starts_with('-'))
+            .filter_map(|line| line.split_whitespace().find(|word| word.starts_with("--")))
+            .filter(|flag| *flag != "--help" && *flag != "--version")
+            .map(|flag| flag.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn options_match_cli() {
+        for (prefix, flags) in OPTIONS.iter() {
+            let mut args = vec!["aureate"];
+            if !prefix.is_empty() {
+                args.push(prefix.trim_end_matches('.'));
+            }
+
+            // Every listed flag is one the cli knows
+            for (name, flag) in flags.iter() {
+                let mut args = args.clone();
+                args.push(flag);
+                if let Err(e) = build_cli().get_matches_from_safe(&args) {
+                    assert!(e.kind != ClapError::UnknownArgument, "{} ({})", flag, name);
+                }
+            }
+
+            // And every flag the cli shows is listed
+            args.push("--help");
+            for flag in help_flags(&args) {
+                assert!(
+                    flags.iter().any(|(_, f)| *f == flag),
+                    "{}{} is not in OPTIONS",
+                    prefix,
+                    flag
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,679 @@
+use {
+    clap::{App, AppSettings, ArgMatches as Matches, Error, ErrorKind as ClapError, Values},
+    serde_json::{map::Map as JMap, value::Value as JsonValue},
+    std::{
+        cell::RefCell,
+        collections::BTreeMap,
+        env,
+        ffi::OsString,
+        fmt, fs,
+        path::{Path, PathBuf},
+        rc::Rc,
+    },
+};
+
+// File names searched for in the working directory, then the home directory
+const CONFIG_NAMES: [&str; 3] = ["aureate.toml", "aureate.yaml", "aureate.yml"];
+
+// Prefix of environment variables setting options, e.g: AUREATE_FORMAT=json,
+// AUREATE_CSV_DELIMITER=; for the csv subcommand's options
+const ENV_PREFIX: &str = "AUREATE_";
+
+// Subcommands (input formats) whose options may be set in a nested table
+const SUBCOMMANDS: [&str; 4] = ["csv", "fixed", "whitespace", "tsv"];
+
+// Where an option's value came from
+#[derive(Debug, Clone)]
+pub enum Origin {
+    CommandLine,
+    Environment,
+    Profile(String, PathBuf),
+    Config(PathBuf),
+    Default,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::CommandLine => write!(f, "command line"),
+            Origin::Environment => write!(f, "environment"),
+            Origin::Profile(name, path) => write!(f, "profile '{}' in {}", name, path.display()),
+            Origin::Config(path) => write!(f, "{}", path.display()),
+            Origin::Default => write!(f, "default"),
+        }
+    }
+}
+
+// An option looked up, by the name it was set with (e.g: --delimiter,
+// AUREATE_CSV_DELIMITER or csv.delimiter), with its value and where it came from
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub value: String,
+    pub origin: Origin,
+}
+
+// The command line flag of every option, by (sub)command prefix then option
+type Flags = BTreeMap<String, BTreeMap<&'static str, String>>;
+
+// Parsed options from every source, in order of precedence. An option
+// is taken from the first layer which explicitly sets it, falling back
+// to the defaults of the last layer
+// Every option looked up is recorded, alongside where its value came from
+#[derive(Clone)]
+pub struct Layered<'a> {
+    layers: Vec<(Origin, Matches<'a>)>,
+    prefix: String,
+    flags: Rc<Flags>,
+    sources: Rc<RefCell<BTreeMap<String, Source>>>,
+    ignored: Rc<Vec<String>>,
+}
+
+impl<'a> Layered<'a> {
+    fn pick(&self, name: &str) -> &Matches<'a> {
+        let (origin, matches) = match self.layers.iter().find(|(_, m)| m.occurrences_of(name) > 0) {
+            Some((origin, matches)) => (origin.clone(), matches),
+            None => (Origin::Default, &self.layers.last().unwrap().1),
+        };
+
+        let values = matches
+            .values_of(name)
+            .map(|values| values.collect::<Vec<&str>>())
+            .unwrap_or_default();
+        let value = match (matches.is_present(name), values.is_empty()) {
+            (false, _) => format!("<unset>"),
+            // A flag
+            (true, true) => format!("true"),
+            (true, false) => values.join(","),
+        };
+        let source = Source {
+            name: self.name_of(name, &origin),
+            value,
+            origin,
+        };
+        self.sources
+            .borrow_mut()
+            .insert(format!("{}{}", self.prefix, name), source);
+
+        matches
+    }
+
+    // How an option is named where it was set
+    fn name_of(&self, name: &str, origin: &Origin) -> String {
+        let flag = self
+            .flags
+            .get(&self.prefix)
+            .and_then(|flags| flags.get(name))
+            .map_or(name, |flag| flag.as_str());
+        let long = flag.trim_start_matches('-');
+
+        match origin {
+            Origin::Environment => format!("{}{}{}", ENV_PREFIX, self.prefix, long)
+                .to_uppercase()
+                .replace(&['-', '.'][..], "_"),
+            Origin::Profile(..) | Origin::Config(_) => format!("{}{}", self.prefix, long),
+            Origin::CommandLine | Origin::Default => {
+                format!("{}{}", self.prefix.replace('.', " "), flag)
+            }
+        }
+    }
+
+    pub fn value_of(&self, name: &str) -> Option<&str> {
+        self.pick(name).value_of(name)
+    }
+
+    pub fn values_of(&self, name: &str) -> Option<Values<'_>> {
+        self.pick(name).values_of(name)
+    }
+
+    pub fn is_present(&self, name: &str) -> bool {
+        self.pick(name).is_present(name)
+    }
+
+    pub fn occurrences_of(&self, name: &str) -> u64 {
+        self.pick(name).occurrences_of(name)
+    }
+
+    // The subcommand of the first layer which used one
+    pub fn subcommand_name(&self) -> Option<&str> {
+        self.layers.iter().find_map(|(_, m)| m.subcommand_name())
+    }
+
+    // The layers which used the given subcommand, if any did
+    pub fn subcommand_matches(&self, name: &str) -> Option<Layered<'a>> {
+        let layers = self
+            .layers
+            .iter()
+            .filter_map(|(origin, m)| {
+                m.subcommand_matches(name)
+                    .map(|sub| (origin.clone(), sub.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        match layers.is_empty() {
+            true => None,
+            false => Some(Layered {
+                layers,
+                prefix: format!("{}{}.", self.prefix, name),
+                flags: Rc::clone(&self.flags),
+                sources: Rc::clone(&self.sources),
+                ignored: Rc::clone(&self.ignored),
+            }),
+        }
+    }
+
+    // Every option looked up so far, by its (sub)command prefixed id
+    pub fn sources(&self) -> Vec<(String, Source)> {
+        self.sources
+            .borrow()
+            .iter()
+            .map(|(id, source)| (id.clone(), source.clone()))
+            .collect()
+    }
+
+    // AUREATE_* environment variables which are not options
+    pub fn ignored(&self) -> Vec<String> {
+        self.ignored.to_vec()
+    }
+}
+
+// Layers the command line over environment variables, the selected profile and
+// the rest of the config file (if any). Environment and config values are parsed
+// by the same cli as the command line, so they are validated the same way.
+// Exits on any error
+pub fn layer(
+    cli: Matches<'static>,
+    build_cli: fn() -> App<'static, 'static>,
+    options: &[(&'static str, &[(&'static str, &'static str)])],
+) -> Layered<'static> {
+    let flags = Rc::new(flags_of(options));
+    let (table, ignored) = env_table(&flags, env::vars_os());
+    let environment = parse_table(&table, &Origin::Environment, build_cli);
+    let path = match cli.value_of("config").or(environment.value_of("config")) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => find_config(),
+    };
+    let profile = cli
+        .value_of("profile")
+        .or(environment.value_of("profile"))
+        .map(|p| p.to_string());
+    let mut layers = vec![
+        (Origin::CommandLine, cli),
+        (Origin::Environment, environment),
+    ];
+    let layered = |layers| Layered {
+        layers,
+        prefix: String::new(),
+        flags,
+        sources: Rc::new(RefCell::new(BTreeMap::new())),
+        ignored: Rc::new(ignored),
+    };
+
+    let path = match (path, &profile) {
+        (Some(path), _) => path,
+        (None, Some(name)) => exit_with(
+            format!("Profile '{}' requested, but no config file was found", name),
+            ClapError::ArgumentNotFound,
+        ),
+        (None, None) => return layered(layers),
+    };
+    let config = load_config(&path);
+
+    if let Some(name) = profile {
+        let table = config
+            .get("profiles")
+            .and_then(|p| p.get(&name))
+            .and_then(|p| p.as_object())
+            .unwrap_or_else(|| {
+                exit_with(
+                    format!("No profile named '{}' in {}", name, path.display()),
+                    ClapError::InvalidValue,
+                )
+            });
+        let origin = Origin::Profile(name, path.clone());
+        let matches = parse_table(table, &origin, build_cli);
+        layers.push((origin, matches));
+    }
+
+    let origin = Origin::Config(path);
+    let matches = parse_table(&config, &origin, build_cli);
+    layers.push((origin, matches));
+
+    layered(layers)
+}
+
+// Tables the flag of every option, by (sub)command prefix then option
+fn flags_of(options: &[(&'static str, &[(&'static str, &'static str)])]) -> Flags {
+    options
+        .iter()
+        .map(|(prefix, flags)| {
+            let flags = flags.iter().map(|(name, flag)| (*name, flag.to_string()));
+            (prefix.to_string(), flags.collect())
+        })
+        .collect()
+}
+
+// Collects AUREATE_* environment variables into a table of options, with
+// 'true' and 'false' setting flags. Variables which do not name a long option
+// are returned separately, and those which are not valid unicode are ignored
+fn env_table<I>(flags: &Flags, vars: I) -> (JMap<String, JsonValue>, Vec<String>)
+where
+    I: Iterator<Item = (OsString, OsString)>,
+{
+    let mut table = JMap::new();
+    let mut subcommands = BTreeMap::new();
+    let mut ignored = Vec::new();
+    let is_option = |prefix: &str, name: &str| {
+        flags.get(prefix).map_or(false, |f| {
+            // Only long flags, so short only options (e.g: -v) never match
+            f.values()
+                .any(|flag| flag.starts_with("--") && flag[2..] == *name)
+        })
+    };
+
+    for (key, value) in vars {
+        let (key, value) = match (key.into_string(), value.into_string()) {
+            (Ok(key), Ok(value)) => (key, value),
+            _ => continue,
+        };
+        let name = match key.starts_with(ENV_PREFIX) {
+            true => key[ENV_PREFIX.len()..].to_lowercase().replace('_', "-"),
+            false => continue,
+        };
+        let value = match value.as_str() {
+            "true" => JsonValue::Bool(true),
+            "false" => JsonValue::Bool(false),
+            _ => JsonValue::String(value),
+        };
+
+        let sub = SUBCOMMANDS.iter().find(|sub| {
+            name.starts_with(&format!("{}-", sub))
+                && is_option(&format!("{}.", sub), &name[sub.len() + 1..])
+        });
+        match sub {
+            Some(sub) => {
+                subcommands
+                    .entry(sub.to_string())
+                    .or_insert_with(JMap::new)
+                    .insert(name[sub.len() + 1..].to_string(), value);
+            }
+            None if is_option("", &name) => {
+                table.insert(name, value);
+            }
+            None => ignored.push(key),
+        };
+    }
+
+    for (sub, options) in subcommands {
+        table.insert(sub, JsonValue::Object(options));
+    }
+    (table, ignored)
+}
+
+fn find_config() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+    let dirs = vec![Some(PathBuf::from(".")), home.map(PathBuf::from)];
+
+    dirs.into_iter()
+        .flatten()
+        .flat_map(|dir| CONFIG_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+// Reads a config file into a table, based on its extension
+fn load_config(path: &Path) -> JMap<String, JsonValue> {
+    let unloadable = |e: &dyn fmt::Display| -> ! {
+        exit_with(
+            format!("Unable to load config {}: {}", path.display(), e),
+            ClapError::InvalidValue,
+        )
+    };
+    let contents = fs::read_to_string(path).unwrap_or_else(|e| unloadable(&e));
+
+    let value: JsonValue = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str::<serde_yaml::Value>(&contents)
+            .map_err(|e| e.to_string())
+            .and_then(|v| serde_json::to_value(v).map_err(|e| e.to_string())),
+        _ => toml::from_str::<toml::Value>(&contents)
+            .map_err(|e| e.to_string())
+            .and_then(|v| serde_json::to_value(v).map_err(|e| e.to_string())),
+    }
+    .unwrap_or_else(|e| unloadable(&e));
+
+    match value {
+        JsonValue::Object(table) => table,
+        // An empty file
+        JsonValue::Null => JMap::new(),
+        _ => unloadable(&"expected a table of options"),
+    }
+}
+
+// Turns a table of options into arguments, and parses them
+fn parse_table(
+    table: &JMap<String, JsonValue>,
+    origin: &Origin,
+    build_cli: fn() -> App<'static, 'static>,
+) -> Matches<'static> {
+    let invalid =
+        |e: String| -> ! { exit_with(format!("In {}: {}", origin, e), ClapError::InvalidValue) };
+
+    let mut args = vec![format!("aureate")];
+    args.extend(to_args(table).unwrap_or_else(|e| invalid(e)));
+
+    // Only one subcommand can be given
+    let subcommands = SUBCOMMANDS
+        .iter()
+        .filter(|sub| table.contains_key(**sub))
+        .collect::<Vec<_>>();
+    if subcommands.len() > 1 {
+        invalid(format!(
+            "only one of {:?} can be set, found tables for {:?}",
+            SUBCOMMANDS, subcommands
+        ))
+    }
+    if let Some(sub) = subcommands.first() {
+        let options = table[**sub]
+            .as_object()
+            .unwrap_or_else(|| invalid(format!("{}: expected a table of options", sub)));
+        args.push(sub.to_string());
+        args.extend(to_args(options).unwrap_or_else(|e| invalid(e)));
+    }
+
+    build_cli()
+        .setting(AppSettings::ColorNever)
+        .get_matches_from_safe(args)
+        .unwrap_or_else(|e| {
+            let reason = e.message.lines().next().unwrap_or_default();
+            invalid(reason.trim_start_matches("error: ").to_string())
+        })
+}
+
+// Converts every option in a table to its long flag,
+// skipping the nested subcommand and profiles tables
+fn to_args(table: &JMap<String, JsonValue>) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        if SUBCOMMANDS.contains(&key.as_str()) || key == "profiles" {
+            continue;
+        }
+        let values = match value {
+            JsonValue::Array(items) => items.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                JsonValue::Bool(true) => args.push(format!("--{}", key)),
+                JsonValue::Bool(false) | JsonValue::Null => (),
+                JsonValue::String(s) => args.push(format!("--{}={}", key, s)),
+                JsonValue::Number(n) => args.push(format!("--{}={}", key, n)),
+                _ => {
+                    return Err(format!(
+                        "{}: expected a value, found a table or nested list",
+                        key
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn exit_with(description: String, kind: ClapError) -> ! {
+    Error::with_description(&description, kind).exit()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        clap::{Arg, SubCommand},
+        serde_json::json,
+    };
+
+    const OPTIONS: [(&str, &[(&str, &str)]); 2] = [
+        ("", &[("format", "--format")]),
+        ("csv.", &[("delimiter_csv", "--delimiter")]),
+    ];
+
+    fn cli() -> App<'static, 'static> {
+        App::new("aureate")
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .takes_value(true)
+                    .default_value("json"),
+            )
+            .subcommand(
+                SubCommand::with_name("csv").arg(
+                    Arg::with_name("delimiter_csv")
+                        .long("delimiter")
+                        .takes_value(true)
+                        .default_value(","),
+                ),
+            )
+    }
+
+    fn table(value: JsonValue) -> JMap<String, JsonValue> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn layered(layers: Vec<(Origin, Matches<'static>)>) -> Layered<'static> {
+        Layered {
+            layers,
+            prefix: String::new(),
+            flags: Rc::new(flags_of(&OPTIONS)),
+            sources: Rc::new(RefCell::new(BTreeMap::new())),
+            ignored: Rc::new(Vec::new()),
+        }
+    }
+
+    // The value of an option, and where it came from
+    fn picked(store: &Layered, name: &str) -> (String, String) {
+        let value = store.value_of(name).unwrap().to_string();
+        let id = format!("{}{}", store.prefix, name);
+        let sources = store.sources();
+        let (_, source) = sources.iter().find(|(i, _)| *i == id).unwrap();
+        (value, source.origin.to_string())
+    }
+
+    #[test]
+    fn config_precedence() {
+        let path = PathBuf::from("aureate.toml");
+        let profile = Origin::Profile(format!("p"), path.clone());
+        let config = Origin::Config(path);
+        let empty = json!({});
+        // Command line, profile, rest of the file, then the expected
+        // format and csv delimiter, each with where it came from
+        let cases = [
+            (
+                vec!["csv"],
+                &empty,
+                &empty,
+                ("json", "default"),
+                (",", "default"),
+            ),
+            (
+                vec![],
+                &empty,
+                &json!({"format": "yaml", "csv": {"delimiter": ";"}}),
+                ("yaml", "aureate.toml"),
+                (";", "aureate.toml"),
+            ),
+            (
+                vec![],
+                &json!({"format": "tsv", "csv": {"delimiter": "|"}}),
+                &json!({"format": "yaml", "csv": {"delimiter": ";"}}),
+                ("tsv", "profile 'p' in aureate.toml"),
+                ("|", "profile 'p' in aureate.toml"),
+            ),
+            (
+                vec!["--format=ndjson", "csv", "--delimiter=:"],
+                &json!({"format": "tsv", "csv": {"delimiter": "|"}}),
+                &json!({"format": "yaml", "csv": {"delimiter": ";"}}),
+                ("ndjson", "command line"),
+                (":", "command line"),
+            ),
+            // Each option is taken from wherever it is set first
+            (
+                vec!["--format=ndjson"],
+                &json!({"csv": {}}),
+                &json!({"format": "yaml", "csv": {"delimiter": ";"}}),
+                ("ndjson", "command line"),
+                (";", "aureate.toml"),
+            ),
+        ];
+
+        for (args, profile_table, config_table, format, delimiter) in cases.iter() {
+            let mut argv = vec!["aureate"];
+            argv.extend(args);
+            let store = layered(vec![
+                (Origin::CommandLine, cli().get_matches_from(argv)),
+                (
+                    profile.clone(),
+                    parse_table(&table((*profile_table).clone()), &profile, cli),
+                ),
+                (
+                    config.clone(),
+                    parse_table(&table((*config_table).clone()), &config, cli),
+                ),
+            ]);
+            let csv = store.subcommand_matches("csv").unwrap();
+            let expected = |(value, origin): &(&str, &str)| (value.to_string(), origin.to_string());
+
+            assert_eq!(picked(&store, "format"), expected(format), "{:?}", args);
+            assert_eq!(
+                picked(&csv, "delimiter_csv"),
+                expected(delimiter),
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn environment_precedence() {
+        let path = PathBuf::from("aureate.toml");
+        let profile = Origin::Profile(format!("p"), path.clone());
+        let config = Origin::Config(path);
+        let file = json!({"format": "yaml", "csv": {"delimiter": ";"}});
+        // Command line, environment, then the expected format
+        // and csv delimiter, each with where it came from
+        let cases = [
+            (
+                vec!["csv"],
+                vec![],
+                ("yaml", "profile 'p' in aureate.toml"),
+                (";", "profile 'p' in aureate.toml"),
+            ),
+            (
+                vec![],
+                vec![("AUREATE_FORMAT", "tsv"), ("AUREATE_CSV_DELIMITER", "|")],
+                ("tsv", "environment"),
+                ("|", "environment"),
+            ),
+            (
+                vec!["--format=ndjson", "csv", "--delimiter=:"],
+                vec![("AUREATE_FORMAT", "tsv"), ("AUREATE_CSV_DELIMITER", "|")],
+                ("ndjson", "command line"),
+                (":", "command line"),
+            ),
+            (
+                vec!["--format=ndjson"],
+                vec![("AUREATE_CSV_DELIMITER", "|")],
+                ("ndjson", "command line"),
+                ("|", "environment"),
+            ),
+        ];
+
+        for (args, vars, format, delimiter) in cases.iter() {
+            let mut argv = vec!["aureate"];
+            argv.extend(args);
+            let vars = vars
+                .iter()
+                .map(|(k, v)| (OsString::from(k), OsString::from(v)));
+            let (environment, _) = env_table(&flags_of(&OPTIONS), vars);
+            let store = layered(vec![
+                (Origin::CommandLine, cli().get_matches_from(argv)),
+                (
+                    Origin::Environment,
+                    parse_table(&environment, &Origin::Environment, cli),
+                ),
+                (
+                    profile.clone(),
+                    parse_table(&table(file.clone()), &profile, cli),
+                ),
+                (config.clone(), parse_table(&JMap::new(), &config, cli)),
+            ]);
+            let csv = store.subcommand_matches("csv").unwrap();
+            let expected = |(value, origin): &(&str, &str)| (value.to_string(), origin.to_string());
+
+            assert_eq!(picked(&store, "format"), expected(format), "{:?}", args);
+            assert_eq!(
+                picked(&csv, "delimiter_csv"),
+                expected(delimiter),
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn environment_names() {
+        let vars = [
+            ("AUREATE_FORMAT", "json"),
+            ("AUREATE_CSV_DELIMITER", ";"),
+            // Options of another (sub)command, or no option at all
+            ("AUREATE_DELIMITER", ","),
+            ("AUREATE_CSV_FORMAT", "yaml"),
+            ("AUREATE_HOME", "/tmp"),
+            ("AUREATE_", "x"),
+            ("PATH", "/bin"),
+        ];
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)));
+        let (environment, ignored) = env_table(&flags_of(&OPTIONS), vars);
+
+        assert_eq!(
+            JsonValue::Object(environment),
+            json!({"format": "json", "csv": {"delimiter": ";"}})
+        );
+        assert_eq!(
+            ignored,
+            vec![
+                "AUREATE_DELIMITER",
+                "AUREATE_CSV_FORMAT",
+                "AUREATE_HOME",
+                "AUREATE_"
+            ]
+        );
+    }
+
+    #[test]
+    fn table_to_args() {
+        let options = table(json!({
+            "append": true,
+            "quiet": false,
+            "output": null,
+            "format": "yaml",
+            "batch-size": 10,
+            "include": ["*.csv", "*.tsv"],
+            "csv": {"delimiter": ";"},
+            "profiles": {"p": {"quiet": true}},
+        }));
+
+        assert_eq!(
+            to_args(&options),
+            Ok(vec![
+                format!("--append"),
+                format!("--format=yaml"),
+                format!("--batch-size=10"),
+                format!("--include=*.csv"),
+                format!("--include=*.tsv"),
+            ])
+        );
+        assert!(to_args(&table(json!({"format": {"a": 1}}))).is_err());
+        assert!(to_args(&table(json!({"include": [["*.csv"]]}))).is_err());
+    }
+}
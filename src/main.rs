@@ -8,19 +8,26 @@ use {
     crate::{
         cli::{generate_cli, ProgramArgs},
         models::{
+            assets::{InputPolicy, ReadFrom},
             error::{ErrorKind, ProgramExit},
+            progress::Progress,
             set_reader,
+            stats::{InputStats, RunStats},
         },
         threads::spawn_workers,
     },
     simplelog::*,
     std::{
-        io::Read as ioRead,
-        sync::mpsc::{sync_channel as syncQueue, Receiver, SyncSender},
+        io::{stdin as cin, Read as ioRead},
+        sync::{
+            mpsc::{sync_channel as syncQueue, Receiver, SyncSender},
+            Arc,
+        },
     },
 };
 
 mod cli;
+mod config;
 mod models;
 mod threads;
 
@@ -33,23 +40,68 @@ fn main() -> ProgramExit<ErrorKind> {
     // Start Pre-program code, do not place anything above these lines
     TermLogger::init(CLI.debug_level(), Config::default()).unwrap();
     info!("CLI options loaded and logger started");
+    for (_, source) in CLI.sources() {
+        debug!(
+            "Option {} = {} (from {})",
+            source.name, source.value, source.origin
+        );
+    }
+    for var in CLI.ignored_env() {
+        debug!("Ignoring {}, which does not name an option", var);
+    }
     // End of Pre-program block
 
     // Channel for sending open input streams (stdin/file handles)
     // number controls how many shall be open at any given time,
     // counting from 0 (i.e: 0 -> 1, 1 -> 2, etc)
+    // Each stream is paired with the counters for its input
     let (tx, rx): (
-        SyncSender<Box<dyn ioRead + Send>>,
-        Receiver<Box<dyn ioRead + Send>>,
+        SyncSender<(Arc<InputStats>, Box<dyn ioRead + Send>)>,
+        Receiver<(Arc<InputStats>, Box<dyn ioRead + Send>)>,
     ) = syncQueue(1);
+    let stats = Arc::new(RunStats::new());
+
+    // Fail before any output is written if an input is unreadable
+    if CLI.input_policy() == InputPolicy::Fail {
+        for source in CLI.reader_list() {
+            drop(set_reader(&CLI, source)?)
+        }
+    }
 
     // Instantiates worker threads
     let reader = spawn_workers(&CLI, rx)?;
 
+    // Live progress reporting, stopped once the workers finish
+    let progress = Progress::start(
+        Arc::clone(&stats),
+        CLI.reader_list().iter().map(|source| source.size()).sum(),
+        CLI.progress_interval(),
+        CLI.progress(),
+        CLI.progress_events(),
+    )?;
+
+    // Sources which could not be opened, and were skipped
+    let mut skipped: Vec<String> = Vec::new();
+
     // Hot loop
     for source in CLI.reader_list() {
-        let read_from: Box<dyn ioRead + Send> = set_reader(source);
-        tx.send(read_from).map_err(|_| {
+        let mut name = source.name();
+        let read_from: Box<dyn ioRead + Send> = match (set_reader(&CLI, source), CLI.input_policy())
+        {
+            (Ok(read_from), _) => read_from,
+            (Err(e), InputPolicy::Fail) => return ProgramExit::Failure(e),
+            (Err(e), InputPolicy::Skip) => {
+                warn!("{}, skipping...", e);
+                skipped.push(source.to_string());
+                continue;
+            }
+            (Err(e), InputPolicy::Stdin) => {
+                warn!("{}, switching to stdin...", e);
+                name = ReadFrom::Stdin.name();
+                Box::new(cin())
+            }
+        };
+        tx.send((stats.track(name), read_from)).map_err(|_| {
             ErrorKind::UnexpectedChannelClose(format!(
                 "reader in |main -> reader| channel has hung up"
             ))
@@ -59,13 +111,36 @@ fn main() -> ProgramExit<ErrorKind> {
     // Signals that that no new input sources will be sent
     drop(tx);
 
-    // Waits for remaining threads to complete
-    reader.join().map_err(|_| {
+    // Waits for remaining threads to complete, only then is output committed
+    let destination = reader.join().map_err(|_| {
         ErrorKind::ThreadFailed(format!(
             "{}",
             std::thread::current().name().unwrap_or("unnamed")
         ))
     })??;
+    drop(progress);
+    destination.commit()?;
+
+    // Input summary
+    let total = CLI.reader_list().len();
+    match skipped.is_empty() {
+        true => info!("Processed {} of {} input(s)", total, total),
+        false => warn!(
+            "Processed {} of {} input(s), skipped: [{}]",
+            total - skipped.len(),
+            total,
+            skipped.join(", ")
+        ),
+    }
+
+    // Run statistics
+    let summary = stats.summary();
+    if CLI.debug_level() != LevelFilter::Off {
+        eprintln!("{}", summary);
+    }
+    if let Some(path) = CLI.stats_json() {
+        summary.write_json(path)?;
+    }
 
     // Return 0
     ProgramExit::Success
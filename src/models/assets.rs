@@ -1,14 +1,23 @@
 use {
-    csv::StringRecord,
-    serde::Serialize,
+    crate::models::{encode::Encoded, fixed::Columns},
+    csv::{StringRecord, StringRecordIter},
+    glob::Pattern,
+    serde::{
+        ser::{SerializeMap, Serializer},
+        Serialize,
+    },
     serde_json::value::Value as JsonValue,
     serde_yaml::Value as YamlValue,
     std::{
         collections::BTreeSet,
         iter,
-        iter::{FromIterator, Iterator},
+        iter::Iterator,
         mem,
-        path::PathBuf,
+        path::{Path, PathBuf},
+        sync::{
+            mpsc::{SendError, Sender, SyncSender, TrySendError},
+            Arc,
+        },
     },
 };
 
@@ -26,8 +35,53 @@ macro_rules! match_with_log {
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum Output {
+    Row(Row),
     Json(JsonValue),
     Yaml(YamlValue),
+    // Already serialized, by the builder pool
+    #[serde(skip_serializing)]
+    Encoded(Encoded),
+}
+
+// A record paired with its headers, serialized directly
+// as a map without building an intermediate value tree
+pub struct Row {
+    headers: Arc<Vec<String>>,
+    record: Record,
+}
+
+impl Row {
+    pub fn new(headers: Arc<Vec<String>>, record: Record) -> Self {
+        Row { headers, record }
+    }
+
+    pub fn headers(&self) -> &Arc<Vec<String>> {
+        &self.headers
+    }
+
+    pub fn fields(&self) -> StringRecordIter<'_> {
+        self.record.data.iter()
+    }
+}
+
+impl Serialize for Row {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Headers are always extended to cover every field of a record
+        let fields = self.record.field_count as usize;
+        let mut map = serializer.serialize_map(Some(fields))?;
+        for (header, field) in self
+            .headers
+            .iter()
+            .take(fields)
+            .zip(self.record.data.iter())
+        {
+            map.serialize_entry(header, field)?;
+        }
+        map.end()
+    }
 }
 
 // Supported read source options
@@ -37,6 +91,86 @@ pub enum ReadFrom {
     Stdin,
 }
 
+// Action taken when an input source cannot be opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputPolicy {
+    // Exit with an error
+    Fail,
+    // Skip the source with a warning
+    Skip,
+    // Read from stdin in its place
+    Stdin,
+}
+
+// How inputs are split into records, selected by subcommand
+#[derive(Debug, Clone)]
+pub enum InputFormat {
+    Csv,
+    FixedWidth {
+        columns: Columns,
+        // The first line is a header, and is skipped
+        header: bool,
+        trim: bool,
+    },
+    Whitespace {
+        // None => split on every run of whitespace
+        max_split: Option<usize>,
+        // The first line holds the headers
+        header: bool,
+    },
+    Tsv {
+        // The first line holds the headers
+        header: bool,
+    },
+}
+
+impl InputFormat {
+    pub fn is_csv(&self) -> bool {
+        match self {
+            InputFormat::Csv => true,
+            _ => false,
+        }
+    }
+}
+
+// Settings for expanding directory and glob inputs into files
+#[derive(Debug, Default)]
+pub struct InputFilter {
+    pub recursive: bool,
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+impl InputFilter {
+    // Checks a file's name against the include and exclude patterns,
+    // exclusions take precedence
+    pub fn allows(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let included = self.include.is_empty() || self.include.iter().any(|p| p.matches(&name));
+        let excluded = self.exclude.iter().any(|p| p.matches(&name));
+
+        included && !excluded
+    }
+}
+
+impl ReadFrom {
+    // Full path of a file, or '-' for stdin
+    pub fn name(&self) -> String {
+        match self {
+            ReadFrom::File(path) => path.display().to_string(),
+            ReadFrom::Stdin => format!("-"),
+        }
+    }
+
+    // Size of a file on disk, unknown for stdin
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            ReadFrom::File(path) => path.metadata().ok().map(|m| m.len()),
+            ReadFrom::Stdin => None,
+        }
+    }
+}
+
 // Displays either 'Stdin' or a file, if file contains non ASCII
 // characters, they are replaced with � (U+FFFD)
 impl std::fmt::Display for ReadFrom {
@@ -53,12 +187,52 @@ impl std::fmt::Display for ReadFrom {
     }
 }
 
+// Supported (de)compression formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    // Identifies a compression format from the magic bytes at the start of a stream
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        match head {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Compression::Xz),
+            // 'BZh' is plausible text, so the block size and first block (or end of stream) magic is checked too
+            [b'B', b'Z', b'h', b'1'..=b'9', 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, ..]
+            | [b'B', b'Z', b'h', b'1'..=b'9', 0x17, 0x72, 0x45, 0x38, 0x50, 0x90, ..] => {
+                Some(Compression::Bzip2)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let display = match *self {
+            Compression::Gzip => "Gzip",
+            Compression::Zstd => "Zstd",
+            Compression::Bzip2 => "Bzip2",
+            Compression::Xz => "Xz",
+        };
+
+        write!(f, "{}", display)
+    }
+}
+
 // Object responsible for ensuring its header list
 // is unique contains at least as many fields as the
-// largest Record field list
+// largest Record field list. The list is shared with
+// every Record sent, and only replaced when it changes
 #[derive(Clone)]
 pub struct Headers {
-    list: Vec<String>,
+    list: Arc<Vec<String>>,
     length: usize,
 }
 
@@ -66,15 +240,18 @@ impl Headers {
     pub fn new(unparsed_list: &StringRecord) -> Self {
         let list: Vec<String> = unparsed_list.iter().map(|csv| csv.to_string()).collect();
         let length = list.len();
-        Headers { list, length }
+        Headers {
+            list: Arc::new(list),
+            length,
+        }
     }
 
     pub fn length(&self) -> u64 {
         self.length as u64
     }
 
-    pub fn list_copy(&self) -> Vec<String> {
-        self.list.clone()
+    pub fn shared(&self) -> Arc<Vec<String>> {
+        Arc::clone(&self.list)
     }
 
     // Logic for deduping and extending the Header list
@@ -125,38 +302,27 @@ impl Headers {
     }
 
     fn transmute(&mut self, replacement: Vec<String>) {
-        let new_list = replacement;
-        let new_length = new_list.len();
+        // Records already sent keep their copy of the old list
+        if replacement == *self.list {
+            return;
+        }
+        let new_length = replacement.len();
 
-        std::mem::replace(&mut self.list, new_list);
+        self.list = Arc::new(replacement);
         self.length = new_length;
     }
 }
 
-// In-program representation of a record and relevant metadata
+// In-program representation of a record and relevant metadata.
+// Fields are kept in the parser's buffer, already validated as UTF-8
 pub struct Record {
-    pub data: Vec<String>,
+    pub data: StringRecord,
     pub field_count: u64,
 }
 
-impl FromIterator<(u64, String)> for Record {
-    fn from_iter<I: IntoIterator<Item = (u64, String)>>(iter: I) -> Self {
-        // Shadowed iter here
-        let iter = iter.into_iter();
-        let mut field_count = 0u64;
-        let mut data = match iter.size_hint() {
-            (_, Some(ub)) => Vec::with_capacity(ub),
-            (lb, None) => Vec::with_capacity(lb),
-        };
-
-        for (c, v) in iter {
-            data.push(v);
-
-            if c > field_count {
-                field_count = c
-            }
-        }
-
+impl Record {
+    pub fn new(data: StringRecord) -> Self {
+        let field_count = data.len() as u64;
         Record { data, field_count }
     }
 }
@@ -166,7 +332,21 @@ impl FromIterator<(u64, String)> for Record {
 pub enum OutputFormat {
     Json,
     JsonPretty,
+    NdJson,
     Yaml,
+    Tsv,
+}
+
+impl OutputFormat {
+    // Describes the shape of a complete output, for error messages
+    pub fn shape(&self) -> &'static str {
+        match *self {
+            OutputFormat::Json | OutputFormat::JsonPretty => "a Json array",
+            OutputFormat::NdJson => "newline delimited Json objects",
+            OutputFormat::Yaml => "a Yaml sequence",
+            OutputFormat::Tsv => "TSV",
+        }
+    }
 }
 
 impl std::fmt::Display for OutputFormat {
@@ -174,13 +354,56 @@ impl std::fmt::Display for OutputFormat {
         let display = match *self {
             OutputFormat::Json => "Json",
             OutputFormat::JsonPretty => "Pretty Json",
+            OutputFormat::NdJson => "Newline delimited Json",
             OutputFormat::Yaml => "Yaml",
+            OutputFormat::Tsv => "TSV",
         };
 
         write!(f, "{}", display)
     }
 }
 
+// Channel sender which announces, at most once, when its receiver is worth consuming:
+// either because the channel filled up, or because the sender is dropped
+pub struct AnnouncingSender<T, M> {
+    tx: SyncSender<T>,
+    announce: Option<(Sender<M>, M)>,
+}
+
+impl<T, M> AnnouncingSender<T, M> {
+    pub fn new(tx: SyncSender<T>, announce: Option<(Sender<M>, M)>) -> Self {
+        AnnouncingSender { tx, announce }
+    }
+
+    pub fn send(&mut self, item: T) -> Result<(), SendError<T>> {
+        if self.announce.is_none() {
+            return self.tx.send(item);
+        }
+
+        match self.tx.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) => {
+                self.announce();
+                self.tx.send(item)
+            }
+            Err(TrySendError::Disconnected(item)) => Err(SendError(item)),
+        }
+    }
+
+    fn announce(&mut self) {
+        if let Some((tx, message)) = self.announce.take() {
+            // Nobody listening is not this sender's problem
+            tx.send(message).ok();
+        }
+    }
+}
+
+impl<T, M> Drop for AnnouncingSender<T, M> {
+    fn drop(&mut self) {
+        self.announce()
+    }
+}
+
 // Custom iterator interface for checking if an item
 // is the first or last item in an iterator
 // returns a tuple -> (is_first, is_last, item): (bool, bool, I: Iterator)
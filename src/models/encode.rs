@@ -0,0 +1,190 @@
+use {
+    crate::models::{
+        assets::{Output, OutputFormat},
+        error::ErrorKind,
+        sink::{ContinueArray, Resume},
+        tsv::{self, TsvWriter},
+    },
+    std::{io::Write as ioWrite, sync::Arc},
+};
+
+// A row serialized by a builder pool worker, so that the writer only has to
+// join rows together. Rows are serialized as elements of the output's array
+// or sequence (e.g: indented, for pretty Json) without separators
+pub struct Encoded {
+    bytes: Vec<u8>,
+    // TSV rows are padded to the width of the header line by the writer
+    headers: Arc<Vec<String>>,
+    fields: usize,
+}
+
+impl Encoded {
+    pub fn new(format: OutputFormat, output: &Output) -> Result<Self, ErrorKind> {
+        let mut bytes = Vec::new();
+        let (headers, fields) = match output {
+            Output::Row(row) => (Arc::clone(row.headers()), row.fields().count()),
+            _ => (Arc::new(Vec::new()), 0),
+        };
+
+        match format {
+            OutputFormat::Json | OutputFormat::NdJson => {
+                serde_json::to_writer(&mut bytes, output).map_err(|e| ErrorKind::from(e))?
+            }
+            // Nested one level, inside the array
+            OutputFormat::JsonPretty => {
+                let mut pretty = Vec::new();
+                serde_json::to_writer_pretty(&mut pretty, output)
+                    .map_err(|e| ErrorKind::from(e))?;
+                indent(&pretty, b"", b"  ", &mut bytes);
+            }
+            // As an item of the sequence, without the document start
+            OutputFormat::Yaml => {
+                let mut document = Vec::new();
+                serde_yaml::to_writer(&mut document, output).map_err(|e| ErrorKind::from(e))?;
+                let body = match document.starts_with(b"---\n") {
+                    true => &document[4..],
+                    false => &document[..],
+                };
+                indent(body, b"- ", b"  ", &mut bytes);
+            }
+            OutputFormat::Tsv => match output {
+                Output::Row(row) => tsv::encode(row.fields(), &mut bytes),
+                _ => unreachable!(),
+            },
+        }
+
+        Ok(Encoded {
+            bytes,
+            headers,
+            fields,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn headers(&self) -> &Arc<Vec<String>> {
+        &self.headers
+    }
+
+    pub fn fields(&self) -> usize {
+        self.fields
+    }
+}
+
+// Copies lines to out, prefixing the first with first and the rest with rest.
+// Line breaks in values are always escaped, so every line is part of the structure
+fn indent(lines: &[u8], first: &[u8], rest: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(first);
+    let mut lines = lines.split(|&b| b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        out.extend_from_slice(line);
+        match lines.peek() {
+            // A trailing line break
+            Some(next) if next.is_empty() => out.push(b'\n'),
+            Some(_) => {
+                out.push(b'\n');
+                out.extend_from_slice(rest);
+            }
+            None => (),
+        }
+    }
+}
+
+// Joins encoded rows into the same output the writer would have serialized
+pub fn write_encoded<W, I>(
+    writer: &mut W,
+    format: OutputFormat,
+    resume: Resume,
+    outputs: I,
+    tsv: &mut TsvWriter,
+    follow: bool,
+) -> Result<(), ErrorKind>
+where
+    W: ioWrite,
+    I: Iterator<Item = Output>,
+{
+    let mut rows = outputs
+        .map(|output| match output {
+            Output::Encoded(encoded) => encoded,
+            _ => unreachable!(),
+        })
+        .peekable();
+
+    match (format, resume) {
+        (OutputFormat::Json, Resume::JsonArray { empty }) => {
+            write_array(&mut ContinueArray::new(writer, !empty), false, rows)
+        }
+        (OutputFormat::Json, _) => write_array(writer, false, rows),
+        (OutputFormat::JsonPretty, Resume::JsonArray { empty }) => {
+            write_array(&mut ContinueArray::new(writer, !empty), true, rows)
+        }
+        (OutputFormat::JsonPretty, _) => write_array(writer, true, rows),
+        (OutputFormat::NdJson, _) => {
+            for row in rows {
+                writer.write_all(row.bytes())?;
+                writer.write_all(b"\n")?;
+                if follow {
+                    writer.flush()?;
+                }
+            }
+            Ok(())
+        }
+        (OutputFormat::Yaml, resume) => {
+            match (resume, rows.peek().is_some()) {
+                // Nothing to add, and an empty sequence cannot extend the existing one
+                (Resume::YamlSequence { empty: false }, false) => (),
+                // Puts back the dropped empty sequence
+                (Resume::YamlSequence { empty: true }, false) => writer.write_all(b"[]\n")?,
+                // Continuing the existing sequence, so no new document
+                (Resume::YamlSequence { .. }, true) => (),
+                (_, false) => writer.write_all(b"---\n[]\n")?,
+                (_, true) => writer.write_all(b"---\n")?,
+            }
+            for row in rows {
+                writer.write_all(row.bytes())?;
+            }
+            Ok(())
+        }
+        (OutputFormat::Tsv, _) => {
+            for row in rows {
+                tsv.write_encoded(writer, row.headers(), row.fields(), row.bytes())?;
+                if follow {
+                    writer.flush()?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// Writes rows as a Json array, laid out as serde_json would
+fn write_array<W, I>(writer: &mut W, pretty: bool, rows: I) -> Result<(), ErrorKind>
+where
+    W: ioWrite,
+    I: Iterator<Item = Encoded>,
+{
+    writer.write_all(b"[")?;
+    let mut first = true;
+    for row in rows {
+        let separator: &[u8] = match (pretty, first) {
+            (false, true) => b"",
+            (false, false) => b",",
+            (true, true) => b"\n  ",
+            (true, false) => b",\n  ",
+        };
+        // A continued array adds its own separator, before the first write
+        if !separator.is_empty() {
+            writer.write_all(separator)?;
+        }
+        writer.write_all(row.bytes())?;
+        first = false;
+    }
+    match (pretty, first) {
+        (true, false) => writer.write_all(b"\n]")?,
+        _ => writer.write_all(b"]")?,
+    }
+
+    Ok(())
+}
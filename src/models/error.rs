@@ -8,6 +8,10 @@ pub(crate) enum ErrorKind {
     ThreadFailed(String),
     // Handles fatal channel closes
     UnexpectedChannelClose(String),
+    // Input source could not be opened
+    UnreadableInput(String),
+    // Output destination could not be created
+    UnwritableOutput(String),
     // Wrapper for any IO / Json serde errors
     Io(ioError),
     // Wrapper for any yaml serde errors
@@ -21,6 +25,8 @@ impl From<ErrorKind> for i32 {
     fn from(err: ErrorKind) -> Self {
         match err {
             ErrorKind::Generic => 1,
+            ErrorKind::UnreadableInput(_) => 1,
+            ErrorKind::UnwritableOutput(_) => 1,
             ErrorKind::Io(_) => 1,
             ErrorKind::ParseYaml(_) => 1,
             ErrorKind::ThreadFailed(_) => 2,
@@ -55,6 +61,13 @@ impl From<serde_json::Error> for ErrorKind {
     }
 }
 
+// csv Error => IO error => ErrorKind
+impl From<csv::Error> for ErrorKind {
+    fn from(err: csv::Error) -> Self {
+        ErrorKind::Io(err.into())
+    }
+}
+
 // Option::None => ErrorKind
 impl From<std::option::NoneError> for ErrorKind {
     fn from(_: std::option::NoneError) -> Self {
@@ -75,6 +88,8 @@ impl std::fmt::Display for ErrorKind {
             ErrorKind::Generic => write!(f, "Generic Error"),
             ErrorKind::ThreadFailed(e) => write!(f, "Thread: {} failed to return", e),
             ErrorKind::UnexpectedChannelClose(e) => write!(f, "A channel quit unexpectedly: {}", e),
+            ErrorKind::UnreadableInput(e) => write!(f, "Unable to read input: {}", e),
+            ErrorKind::UnwritableOutput(e) => write!(f, "Unable to write output: {}", e),
             ErrorKind::Io(e) => write!(f, "An underlying IO error occurred: {}", e),
             ErrorKind::ParseYaml(e) => write!(f, "An underlying IO (yml) error occurred: {}", e),
         }
@@ -88,6 +103,8 @@ impl Error for ErrorKind {
             ErrorKind::Generic => None,
             ErrorKind::ThreadFailed(_) => None,
             ErrorKind::UnexpectedChannelClose(_) => None,
+            ErrorKind::UnreadableInput(_) => None,
+            ErrorKind::UnwritableOutput(_) => None,
             ErrorKind::Io(e) => Some(e),
             ErrorKind::ParseYaml(e) => Some(e),
         }
@@ -0,0 +1,250 @@
+use {
+    crate::models::{error::ErrorKind, records::Lines},
+    csv::ByteRecord,
+    std::{fs, io::Read as ioRead},
+};
+
+// A single column of a fixed-width input
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    // Offset of the column's first character, from 0
+    pub start: usize,
+    // None => the column runs to the end of the line
+    pub width: Option<usize>,
+}
+
+impl Column {
+    // Parses 'name:start:width', where start counts from 1
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.rsplitn(3, ':');
+        let (width, start, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(width), Some(start), Some(name)) if !name.is_empty() => (width, start, name),
+            _ => {
+                return Err(format!(
+                    "'{}' is not a column, expected NAME:START:WIDTH",
+                    spec
+                ))
+            }
+        };
+
+        match (start.parse::<usize>(), width.parse::<usize>()) {
+            (Ok(start), Ok(width)) if start > 0 && width > 0 => Ok(Column {
+                name: name.to_string(),
+                start: start - 1,
+                width: Some(width),
+            }),
+            _ => Err(format!(
+                "'{}': start and width must be whole numbers, from 1",
+                spec
+            )),
+        }
+    }
+
+    // Slices this column from a line, or an empty field
+    // if the line ends before it
+    fn slice<'a>(&self, line: &'a [u8], boundaries: &Option<Vec<usize>>) -> &'a [u8] {
+        let offset = |chars: usize| match boundaries {
+            Some(b) => b.get(chars).copied().unwrap_or(line.len()),
+            None => chars.min(line.len()),
+        };
+        let start = offset(self.start);
+        let end = self.width.map_or(line.len(), |w| offset(self.start + w));
+
+        &line[start..end]
+    }
+}
+
+// How the columns of a fixed-width input are found
+#[derive(Debug, Clone)]
+pub enum Columns {
+    Given(Vec<Column>),
+    // From the header line
+    Detect,
+}
+
+// Reads columns from a spec file, one NAME:START:WIDTH per line.
+// Blank lines and lines starting with '#' are ignored
+pub fn load_spec(path: &str) -> Result<Vec<Column>, ErrorKind> {
+    let unreadable = |e: String| ErrorKind::UnreadableInput(format!("{}: {}", path, e));
+    let contents = fs::read_to_string(path).map_err(|e| unreadable(e.to_string()))?;
+
+    let columns = contents
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| Column::parse(l))
+        .collect::<Result<Vec<Column>, String>>()
+        .map_err(unreadable)?;
+
+    match columns.is_empty() {
+        true => Err(unreadable(format!("no columns found"))),
+        false => Ok(columns),
+    }
+}
+
+// Columns start wherever a header name does, and run until the next one.
+// The first column always starts at the beginning of the line, and the
+// last runs to its end
+fn detect_columns(header: &str) -> Vec<Column> {
+    let chars = header.chars().collect::<Vec<char>>();
+    let mut starts = (0..chars.len())
+        .filter(|&i| !chars[i].is_whitespace() && (i == 0 || chars[i - 1].is_whitespace()))
+        .collect::<Vec<usize>>();
+    if let Some(first) = starts.first_mut() {
+        *first = 0;
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = starts.get(i + 1).copied();
+            let name = chars[start..end.unwrap_or(chars.len())]
+                .iter()
+                .collect::<String>();
+            Column {
+                name: name.trim().to_string(),
+                start,
+                width: end.map(|end| end - start),
+            }
+        })
+        .collect()
+}
+
+// Splits a fixed-width source into records, one per (non-empty) line
+pub struct FixedWidth<R> {
+    lines: Lines<R>,
+    columns: Vec<Column>,
+    trim: bool,
+}
+
+impl<R: ioRead> FixedWidth<R> {
+    // Reads the header line, if the input has one, returning the reader
+    // alongside the column names
+    pub fn new(
+        source: R,
+        columns: &Columns,
+        header: bool,
+        trim: bool,
+    ) -> Result<(Self, ByteRecord), ErrorKind> {
+        let mut lines = Lines::new(source, true);
+        let columns = match (columns, header) {
+            (Columns::Given(columns), false) => columns.clone(),
+            (Columns::Given(columns), true) => {
+                lines.next_line()?;
+                columns.clone()
+            }
+            (Columns::Detect, _) => match lines.next_line()? {
+                Some(header) => detect_columns(&String::from_utf8_lossy(header)),
+                // An empty input
+                None => Vec::new(),
+            },
+        };
+
+        let names = columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<ByteRecord>();
+        Ok((
+            FixedWidth {
+                lines,
+                columns,
+                trim,
+            },
+            names,
+        ))
+    }
+}
+
+impl<R: ioRead> Iterator for FixedWidth<R> {
+    type Item = Result<ByteRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let text = match self.lines.next_line() {
+            Ok(Some(text)) => text,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e.to_string())),
+        };
+
+        // Columns are counted in characters, unless the line is not valid
+        // UTF-8, in which case bytes are used and the record is left to UTF-8
+        // validation
+        let boundaries = match (text.is_ascii(), std::str::from_utf8(text)) {
+            (false, Ok(s)) => Some(s.char_indices().map(|(i, _)| i).collect::<Vec<usize>>()),
+            _ => None,
+        };
+
+        let mut record = self
+            .columns
+            .iter()
+            .map(|c| c.slice(text, &boundaries))
+            .collect::<ByteRecord>();
+        if self.trim {
+            record.trim();
+        }
+        record.set_position(Some(self.lines.position()));
+
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_columns() {
+        // (spec, expected name, start from 0 and width)
+        let cases = [
+            ("name:1:5", Some(("name", 0, 5))),
+            ("id:10:1", Some(("id", 9, 1))),
+            // Only the last two parts are positions
+            ("a:b:3:2", Some(("a:b", 2, 2))),
+            ("name:0:5", None),
+            ("name:1:0", None),
+            ("name:-1:5", None),
+            ("name:1:x", None),
+            ("name:1", None),
+            (":1:5", None),
+            ("name:1:5:", None),
+        ];
+
+        for (spec, expected) in cases.iter() {
+            let parsed = Column::parse(spec)
+                .ok()
+                .map(|c| (c.name, c.start, c.width.unwrap()));
+            let expected = expected.map(|(name, start, width)| (name.to_string(), start, width));
+            assert_eq!(parsed, expected, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn overlapping_and_out_of_order_spans() {
+        // (columns, expected fields of the line "abcdefgh")
+        let cases: [(&[&str], &[&str]); 5] = [
+            (&["a:1:3", "b:4:5"], &["abc", "defgh"]),
+            // Columns are read in the order given
+            (&["b:4:2", "a:1:3"], &["de", "abc"]),
+            // Overlapping columns share bytes
+            (&["a:1:4", "b:3:4"], &["abcd", "cdef"]),
+            (&["a:2:2", "b:1:8"], &["bc", "abcdefgh"]),
+            // Past the end of the line
+            (&["a:7:5", "b:20:1"], &["gh", ""]),
+        ];
+
+        for (specs, expected) in cases.iter() {
+            let columns = specs
+                .iter()
+                .map(|s| Column::parse(s).unwrap())
+                .collect::<Vec<Column>>();
+            let (mut reader, _) =
+                FixedWidth::new(&b"abcdefgh\n"[..], &Columns::Given(columns), false, false)
+                    .unwrap();
+            let record = reader.next().unwrap().unwrap();
+            let expected = expected.iter().map(|f| f.as_bytes());
+
+            assert!(record.iter().eq(expected), "{:?}: {:?}", specs, record);
+        }
+    }
+}
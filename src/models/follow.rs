@@ -0,0 +1,269 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{Read as ioRead, Result as ioResult, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread::sleep,
+    time::Duration,
+};
+
+// How long to wait for a followed file to grow, before checking again
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Reads a file which is still being written, like 'tail -F'. Reaching the
+// end of the file waits for more to be written, rather than ending the read.
+// A truncated file is read again from the start, and a replaced (rotated)
+// file is finished before its replacement is opened. Either way, a first
+// line repeating the original file's (e.g: a header row) is skipped
+pub struct Follow {
+    path: PathBuf,
+    file: File,
+    id: Option<(u64, u64)>,
+    position: u64,
+    // Last byte read, a partial line is ended before switching files
+    last: u8,
+    // First line of the original file, and whether it is complete
+    header: (Vec<u8>, bool),
+    // Bytes read but not yet returned
+    pending: Vec<u8>,
+    // Where the first line of a new file starts in pending, until it is complete
+    checking: Option<usize>,
+}
+
+impl Follow {
+    pub fn new(path: &Path, file: File) -> Self {
+        let id = file.metadata().ok().and_then(|m| file_id(&m));
+        Follow {
+            path: path.to_path_buf(),
+            file,
+            id,
+            position: 0,
+            last: b'\n',
+            header: (Vec::new(), false),
+            pending: Vec::new(),
+            checking: None,
+        }
+    }
+
+    fn take(&mut self, bytes: &[u8]) {
+        self.position += bytes.len() as u64;
+        self.last = bytes[bytes.len() - 1];
+        if !self.header.1 {
+            let end = bytes.iter().position(|&b| b == b'\n');
+            let line = &bytes[..end.map_or(bytes.len(), |end| end + 1)];
+            self.header.0.extend_from_slice(line);
+            self.header.1 = end.is_some();
+        }
+        self.pending.extend_from_slice(bytes);
+
+        if let Some(start) = self.checking {
+            if let Some(end) = self.pending[start..].iter().position(|&b| b == b'\n') {
+                let line = start..start + end + 1;
+                if self.pending[line.clone()] == self.header.0[..] {
+                    self.pending.drain(line);
+                }
+                self.checking = None;
+            }
+        }
+    }
+
+    // Starts reading from the start of the (possibly new) file
+    fn restart(&mut self) {
+        self.position = 0;
+        // Ends any partial line left by the previous file
+        if self.last != b'\n' {
+            self.pending.push(b'\n');
+            self.last = b'\n';
+        }
+        // Only a complete first line is looked for again
+        self.checking = match self.header {
+            (_, true) => Some(self.pending.len()),
+            _ => None,
+        };
+        self.header.1 = true;
+    }
+}
+
+impl ioRead for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> ioResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if !self.pending.is_empty() && self.checking.is_none() {
+                let count = buf.len().min(self.pending.len());
+                buf[..count].copy_from_slice(&self.pending[..count]);
+                self.pending.drain(..count);
+                return Ok(count);
+            }
+
+            let read = self.file.read(buf)?;
+            if read > 0 {
+                self.take(&buf[..read]);
+                continue;
+            }
+
+            match fs::metadata(&self.path) {
+                Ok(meta) if file_id(&meta) != self.id => match File::open(&self.path) {
+                    Ok(file) => {
+                        // Anything written just before the replacement is read first
+                        let read = self.file.read(buf)?;
+                        if read > 0 {
+                            self.take(&buf[..read]);
+                            continue;
+                        }
+
+                        info!("{} was replaced, following the new file...", self.path.display());
+                        self.id = file_id(&meta);
+                        self.file = file;
+                        self.restart();
+                    }
+                    Err(_) => sleep(POLL_INTERVAL),
+                },
+                Ok(ref meta) if meta.len() < self.position => {
+                    info!("{} was truncated, reading from the start...", self.path.display());
+                    self.file.seek(SeekFrom::Start(0))?;
+                    self.restart();
+                }
+                // Not grown yet, or moved away and not yet replaced
+                _ => sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+// Identifies the file at a path, so that its replacement can be noticed
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+// Without a stable file id, only truncation is noticed
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{io::Write as ioWrite, sync::mpsc, thread},
+    };
+
+    enum Step {
+        Append(&'static str),
+        Truncate,
+        Replace(&'static str),
+    }
+
+    // Collects what has been read, until it is the expected output
+    fn expect(rx: &mpsc::Receiver<Vec<u8>>, read: &mut Vec<u8>, expected: &[u8], case: usize) {
+        while read.len() < expected.len() {
+            match rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(bytes) => read.extend_from_slice(&bytes),
+                Err(_) => break,
+            }
+        }
+        assert_eq!(
+            String::from_utf8_lossy(read),
+            String::from_utf8_lossy(expected),
+            "case {}",
+            case
+        );
+    }
+
+    #[test]
+    fn truncated_and_replaced() {
+        use Step::*;
+        // Changes to the followed file, each with what is read afterwards
+        let cases: [&[(Step, &str)]; 6] = [
+            &[
+                (Append("a,b\n1,2\n"), "a,b\n1,2\n"),
+                (Append("3,4\n"), "3,4\n"),
+                (Truncate, ""),
+                // The header is not repeated
+                (Append("a,b\n5,6\n"), "5,6\n"),
+            ],
+            &[
+                (Append("a,b\n1,2\n"), "a,b\n1,2\n"),
+                // Written just before the replacement
+                (Append("3,4\n"), ""),
+                (Replace("a,b\n5,6\n"), "3,4\n5,6\n"),
+                (Append("7,8\n"), "7,8\n"),
+            ],
+            // A partial line is ended before switching files
+            &[
+                (Append("a,b\n1,2\n3"), "a,b\n1,2\n3"),
+                (Append(",4"), ",4"),
+                (Replace("a,b\n5,6\n"), "\n5,6\n"),
+            ],
+            // A different first line is kept
+            &[
+                (Append("a,b\n1,2\n"), "a,b\n1,2\n"),
+                (Replace("c,d\n5,6\n"), "c,d\n5,6\n"),
+            ],
+            // The repeated first line is held until it is complete
+            &[
+                (Append("a,b\n1,2\n"), "a,b\n1,2\n"),
+                (Replace("a,"), ""),
+                (Append("b\n5,6\n"), "5,6\n"),
+                (Truncate, ""),
+                (Append("a,b\n"), ""),
+                (Append("7,8\n"), "7,8\n"),
+            ],
+            // Only a complete first line is looked for again
+            &[
+                (Append("a,b"), "a,b"),
+                (Truncate, "\n"),
+                (Append("a,b\n1,2\n"), "a,b\n1,2\n"),
+            ],
+        ];
+
+        for (i, steps) in cases.iter().enumerate() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("followed.csv");
+            let mut file = File::create(&path).unwrap();
+
+            // Read with a small buffer, as reads block until there is more input
+            let mut follow = Follow::new(&path, File::open(&path).unwrap());
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let mut buf = [0u8; 3];
+                while let Ok(read) = follow.read(&mut buf) {
+                    if tx.send(buf[..read].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let mut read = Vec::new();
+            let mut expected = Vec::new();
+            for (step, output) in steps.iter() {
+                match step {
+                    Append(bytes) => file.write_all(bytes.as_bytes()).unwrap(),
+                    Truncate => {
+                        file.set_len(0).unwrap();
+                        file.seek(SeekFrom::Start(0)).unwrap();
+                        // Truncation is only noticed while the file is shorter
+                        sleep(POLL_INTERVAL * 3);
+                    }
+                    Replace(bytes) => {
+                        let next = dir.path().join("next.csv");
+                        fs::write(&next, bytes).unwrap();
+                        fs::rename(&next, &path).unwrap();
+                        file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+                    }
+                }
+                expected.extend_from_slice(output.as_bytes());
+                expect(&rx, &mut read, &expected, i);
+            }
+
+            // Nothing is read twice
+            sleep(POLL_INTERVAL * 2);
+            expect(&rx, &mut read, &expected, i);
+            assert!(rx.try_recv().is_err(), "case {}", i);
+        }
+    }
+}
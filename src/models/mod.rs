@@ -3,157 +3,353 @@ use {
         cli::ProgramArgs,
         match_with_log,
         models::{
-            assets::{Headers, ReadFrom, Record},
+            assets::{AnnouncingSender, Headers, InputFilter, ReadFrom, Record},
             error::ErrorKind,
+            follow::Follow,
+            records::RecordSource,
+            sink::{Destination, Encoder, Resume},
+            sniff::{sniff, Dialect, SNIFF_BYTES},
+            source::{decode_source, decompress_source, peek},
+            stats::{Counted, InputStats},
         },
     },
-    csv::ReaderBuilder,
+    csv::StringRecord,
+    memmap2::Mmap,
     serde_json::{map::Map as JMap, value::Value as JsonValue},
     serde_yaml::{Mapping as YMap, Value as YamlValue},
     std::{
         boxed::Box,
-        fs::{File, OpenOptions},
-        io::{stdin as cin, stdout as cout, Read as ioRead, Write as ioWrite},
-        path::PathBuf,
-        sync::mpsc::SyncSender,
+        fs::read_dir,
+        fs::File,
+        io::{stdin as cin, stdout as cout, Cursor, Read as ioRead},
+        mem,
+        path::{Path, PathBuf},
+        sync::Arc,
         vec::Vec,
     },
 };
 
 pub mod assets;
+pub mod encode;
 pub mod error;
+pub mod fixed;
+pub mod follow;
+pub mod progress;
+pub mod records;
+pub mod sink;
+pub mod sniff;
+pub mod source;
+pub mod split;
+pub mod stats;
+pub mod tsv;
+pub mod whitespace;
 
-// Determines write destination from runtime args
-// w: (_, bool), true => append, false => create
-pub fn get_writer(w: &(Option<String>, bool)) -> Box<dyn ioWrite> {
-    match w {
+// Determines write destination from runtime args, wrapping it in
+// a compressing encoder if requested. Files are staged, and must
+// be committed after all output has been written
+// writer: (_, bool), true => append, false => create
+// Also returns how the writer should continue any appended to output
+pub(crate) fn get_writer(opts: &ProgramArgs) -> Result<(Encoder<Destination>, Resume), ErrorKind> {
+    let compression = opts.compression();
+    if let Some((format, level)) = compression {
+        info!(
+            "Compressing output with {} (level: {})...",
+            format,
+            level.map_or(format!("default"), |l| l.to_string())
+        );
+    }
+
+    let (destination, resume) = match opts.writer() {
+        // Followed input never finishes, so its output cannot be staged
+        (Some(file_name), append) if opts.follow() => match_with_log!(
+            Destination::direct(
+                Path::new(file_name),
+                match append {
+                    true => Some(opts.output_type()),
+                    false => None,
+                },
+                opts.lock()
+            )?,
+            info!("Attempting to write to {} in place...", file_name)
+        ),
         (Some(file_name), false) => match_with_log!(
-            match File::create(file_name).ok() {
-                Some(file) => match_with_log!(Box::new(file), info!("Success!")),
-                None => match_with_log!(Box::new(cout()), warn!("Failed! Switching to stdout...")),
-            },
+            Destination::stage(Path::new(file_name), None, opts.lock())?,
             info!("Attempting to create {}...", file_name)
         ),
+        (Some(file_name), true) if compression.is_some() => {
+            return Err(ErrorKind::UnwritableOutput(format!(
+                "{}: compressed output cannot be appended to",
+                file_name
+            )))
+        }
         (Some(file_name), true) => match_with_log!(
-            match OpenOptions::new().append(true).open(file_name) {
-                Ok(file) => match_with_log!(Box::new(file), info!("Success!")),
-                Err(e) => match_with_log!(
-                    Box::new(cout()),
-                    warn!("Unable to open file: {}, switching to stdout...", e)
-                ),
-            },
+            Destination::stage(Path::new(file_name), Some(opts.output_type()), opts.lock())?,
             info!("Attempting to append to {}...", file_name)
         ),
         (None, _) => match_with_log!(
-            Box::new(cout()),
+            (Destination::Stdout(cout()), Resume::Fresh),
             info!("No file detected, defaulting to stdout...")
         ),
-    }
+    };
+
+    Ok((Encoder::new(destination, compression)?, resume))
 }
 
-// Helper function for generating a list of read sources at runtime
-pub fn get_reader(r: Option<&str>) -> Option<ReadFrom> {
+// Helper function for generating a list of read sources at runtime.
+// Paths are not checked here, failing to open them is handled by set_reader
+pub fn get_reader(r: Option<&str>) -> ReadFrom {
     match r {
-        Some("-") => Some(ReadFrom::Stdin),
-        Some(file_name) => {
-            let path = PathBuf::from(file_name);
-            if path.is_file() {
-                Some(ReadFrom::File(path))
-            } else {
-                None
-            }
+        Some("-") => ReadFrom::Stdin,
+        Some(file_name) => ReadFrom::File(PathBuf::from(file_name)),
+        None => ReadFrom::Stdin,
+    }
+}
+
+// Expands an input argument into read sources. Directories and glob patterns
+// are expanded into their files in sorted order, everything else is handed
+// to get_reader as is
+pub fn expand_reader(r: &str, filter: &InputFilter) -> Vec<ReadFrom> {
+    let path = Path::new(r);
+    let is_glob = r.contains(|c| c == '*' || c == '?' || c == '[');
+
+    let mut files = match (path.is_dir(), is_glob && !path.exists()) {
+        (true, _) => {
+            let mut files = Vec::new();
+            walk_dir(path, filter.recursive, &mut files);
+            files
         }
-        None => Some(ReadFrom::Stdin),
+        (false, true) => match glob::glob(r) {
+            Ok(paths) => paths
+                .filter_map(|entry| entry.ok())
+                .filter(|p| p.is_file())
+                .collect(),
+            Err(_) => Vec::new(),
+        },
+        (false, false) => return vec![get_reader(Some(r))],
+    };
+
+    files.retain(|p| filter.allows(p));
+    files.sort();
+
+    match files.is_empty() {
+        // Nothing matched, leave it to get_reader. A directory is then
+        // rejected when opened, and a pattern fails as a missing file
+        true => vec![get_reader(Some(r))],
+        false => files.into_iter().map(|p| ReadFrom::File(p)).collect(),
     }
 }
 
-// Opens a read source, defaults to stdin if source errors
-pub fn set_reader(src: &Option<ReadFrom>) -> Box<dyn ioRead + Send> {
+// Collects the files in a directory, descending into subdirectories if recursive.
+// Unreadable directories and entries are skipped, as are symlinked directories,
+// which may link back to a parent
+fn walk_dir(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        // Unlike path.is_dir(), does not follow symlinks
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if path.is_file() {
+            files.push(path)
+        } else if recursive && is_dir {
+            walk_dir(&path, recursive, files)
+        }
+    }
+}
+
+// Opens a read source, the caller decides what to do
+// with sources which fail based on the input error policy
+pub fn set_reader(opts: &ProgramArgs, src: &ReadFrom) -> Result<Box<dyn ioRead + Send>, ErrorKind> {
     match src {
-        Some(s) => match s {
-            ReadFrom::File(path) => match_with_log!(
-                match File::open(path) {
-                    Ok(f) => match_with_log!(Box::new(f), info!("Success!")),
-                    Err(e) => match_with_log!(
-                        Box::new(cin()),
-                        warn!("Failed! {}, switching to stdin...", e)
+        ReadFrom::File(path) => match_with_log!(
+            match File::open(path) {
+                // Opening succeeds on some platforms, but reading does not
+                Ok(_) if path.is_dir() => Err(ErrorKind::UnreadableInput(format!(
+                    "{}: is a directory, with no input files in it",
+                    path.display()
+                ))),
+                Ok(f) if opts.follow() => match_with_log!(
+                    Ok(Box::new(Follow::new(path, f))),
+                    info!("Success! (following)")
+                ),
+                Ok(f) if opts.mmap() => match map_file(&f) {
+                    Some(map) => match_with_log!(
+                        Ok(Box::new(Cursor::new(map))),
+                        info!("Success! (memory mapped)")
                     ),
+                    None => match_with_log!(Ok(Box::new(f)), info!("Success!")),
                 },
-                info!("Attempting to read from {:?}...", path)
-            ),
-            ReadFrom::Stdin => match_with_log!(Box::new(cin()), info!("Reading CSV from stdin...")),
-        },
-        None => match_with_log!(
-            Box::new(cin()),
-            info!("No input source found, defaulting to stdin...")
+                Ok(f) => match_with_log!(Ok(Box::new(f)), info!("Success!")),
+                Err(e) => Err(ErrorKind::UnreadableInput(format!(
+                    "{}: {}",
+                    path.display(),
+                    e
+                ))),
+            },
+            info!("Attempting to read from {:?}...", path)
+        ),
+        ReadFrom::Stdin => match_with_log!(Ok(Box::new(cin())), info!("Reading CSV from stdin...")),
+    }
+}
+
+// Memory maps a file, if it is a non-empty regular file. Pipes, special
+// files and failed maps return None, and should be read normally
+fn map_file(file: &File) -> Option<Mmap> {
+    match file.metadata() {
+        Ok(meta) if meta.is_file() && meta.len() > 0 => (),
+        _ => return None,
+    }
+
+    // Safety: the map is read only, but another process truncating or
+    // writing to the file while it is mapped is not guarded against
+    match unsafe { Mmap::map(file) } {
+        Ok(map) => Some(map),
+        Err(e) => match_with_log!(
+            None,
+            debug!(
+                "Failed to memory map input: {}, falling back to buffered reads",
+                e
+            )
         ),
     }
 }
 
 // Parses CSV source into a manipulatable format
 // that other functions can use to build JSON/YAML structures
-pub(crate) fn parse_csv_source<R>(
+pub(crate) fn parse_csv_source<R, M>(
     opts: &ProgramArgs,
     source: R,
-    tx_builder: SyncSender<(Vec<String>, Record)>,
+    stats: &Arc<InputStats>,
+    mut tx_builder: AnnouncingSender<Vec<(Arc<Vec<String>>, Record)>, M>,
 ) -> Result<(), ErrorKind>
 where
-    R: ioRead,
+    R: ioRead + Send + 'static,
 {
-    let mut rdr = ReaderBuilder::new()
-        .delimiter(opts.delimiter())
-        .flexible(opts.flexible())
-        .escape(opts.escape())
-        .comment(opts.comment())
-        .quote(opts.quote())
-        .trim(opts.trim_settings())
-        .double_quote(opts.quote_settings().0)
-        .quoting(opts.quote_settings().1)
-        .from_reader(source);
-
-    let mut headers: Headers = Headers::new(rdr.headers().unwrap());
+    stats.begin();
+    let source = Counted::new(source, Arc::clone(stats));
+    // Detecting compression would wait for the first bytes of a followed input
+    let source = match opts.follow() {
+        true => decode_source(opts, Box::new(source))?,
+        false => decode_source(opts, decompress_source(Box::new(source))?)?,
+    };
+
+    // Options the user did not set are guessed from the input, if requested
+    let (source, dialect): (Box<dyn ioRead + Send>, Dialect) = match opts.sniff() {
+        true if opts.delimiter_str().is_some() => match_with_log!(
+            (source, Dialect::from_opts(opts)),
+            warn!("Sniffing is not supported with a multi-character delimiter, skipping...")
+        ),
+        true if opts.follow() => match_with_log!(
+            (source, Dialect::from_opts(opts)),
+            warn!("Sniffing is not supported when following an input, skipping...")
+        ),
+        true if !opts.input_format().is_csv() => match_with_log!(
+            (source, Dialect::from_opts(opts)),
+            warn!("Sniffing is only supported for CSV input, skipping...")
+        ),
+        true => {
+            let (sample, source) = peek(source, SNIFF_BYTES)?;
+            let dialect = Dialect::from_opts(opts).merge(sniff(&sample), opts);
+            info!("Sniffed CSV dialect: {}", dialect);
+            (Box::new(source), dialect)
+        }
+        false => (source, Dialect::from_opts(opts)),
+    };
+
+    let source = RecordSource::new(opts, dialect, source)?;
+
+    // Headers are always decoded lossily, as there is no record to skip.
+    // Without a header row, every header is generated
+    let mut headers: Headers = match source.headers {
+        Some(hdr) => match StringRecord::from_byte_record(hdr) {
+            Ok(hdr) => Headers::new(&hdr),
+            Err(e) => match_with_log!(
+                Headers::new(&StringRecord::from_byte_record_lossy(e.into_byte_record())),
+                warn!("Headers contain invalid UTF-8, replacing invalid sequences...")
+            ),
+        },
+        None => Headers::new(&StringRecord::new()),
+    };
     headers.extend(0);
 
+    let lossy = opts.lossy();
+
     // Hot loop
-    let res = rdr
-        .records()
+    let res = source
+        .records
         // Skip rows which error based on the CSV parser options, with a warning
-        .filter_map(|result| match result {
-            Ok(r) => Some(r),
-            Err(e) => match_with_log!(None, warn!("Failed to parse record: {}, skipping...", e)),
+        .filter_map(|result| {
+            stats.read(1);
+            match result {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    stats.reject(1);
+                    match_with_log!(None, warn!("Failed to parse record: {}, skipping...", e))
+                }
+            }
         })
-        // Parse CSV into a useable format and add metadata necessary for the conversion
-        .map(|record| {
-            record
-                .iter()
-                .map(|field| field.to_string())
-                .scan(0u64, |count, record| {
-                    *count += 1;
-                    Some((*count, record))
-                })
-                .collect::<Record>()
+        // Skip rows containing invalid UTF-8, unless the user has requested lossy decoding.
+        // Validation happens in place, valid records are never copied
+        .filter_map(|record| {
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            match StringRecord::from_byte_record(record) {
+                Ok(r) => Some(r),
+                Err(e) if lossy => Some(StringRecord::from_byte_record_lossy(e.into_byte_record())),
+                Err(e) => {
+                    stats.reject(1);
+                    match_with_log!(
+                        None,
+                        warn!(
+                            "Failed to parse record on line {}: invalid UTF-8 in field {}, skipping...",
+                            line,
+                            e.utf8_error().field() + 1
+                        )
+                    )
+                }
+            }
         })
+        // Add metadata necessary for the conversion
+        .map(|record| Record::new(record))
         .map(|wrapper| {
             let record_length = wrapper.field_count;
             if headers.length() < record_length {
                 headers.extend(record_length)
             }
 
-            (headers.list_copy(), wrapper)
+            (headers.shared(), wrapper)
         });
-    for (header, record) in res {
-        tx_builder.send((header, record)).map_err(|_| {
+
+    // Records are sent in batches, once either limit is reached
+    let (batch_size, batch_bytes) = (opts.batch_size(), opts.batch_bytes());
+    let mut send = |batch| {
+        tx_builder.send(batch).map_err(|_| {
             ErrorKind::UnexpectedChannelClose(format!(
                 "builder in |reader -> builder| channel has hung up"
             ))
-        })?;
+        })
+    };
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut bytes = 0usize;
+    for (header, record) in res {
+        bytes += record.data.as_byte_record().as_slice().len();
+        batch.push((header, record));
+        if batch.len() >= batch_size || bytes >= batch_bytes {
+            send(mem::replace(&mut batch, Vec::with_capacity(batch_size)))?;
+            bytes = 0;
+        }
+    }
+    if !batch.is_empty() {
+        send(batch)?;
     }
 
     Ok(())
 }
 
 // Helper function for building Json compliant memory representations
-pub fn build_json(hdr: Vec<String>, record: Record) -> JsonValue {
+pub fn build_json(hdr: &[String], record: Record) -> JsonValue {
     let mut headers = hdr.iter().take(record.field_count as usize);
     let mut records = record.data.iter();
     let mut output = JMap::new();
@@ -182,7 +378,7 @@ pub fn build_json(hdr: Vec<String>, record: Record) -> JsonValue {
 }
 
 // Helper function for building Yaml compliant memory representations
-pub fn build_yaml(hdr: Vec<String>, record: Record) -> YamlValue {
+pub fn build_yaml(hdr: &[String], record: Record) -> YamlValue {
     let mut headers = hdr.iter().take(record.field_count as usize);
     let mut records = record.data.iter();
     let mut output = YMap::new();
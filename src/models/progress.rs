@@ -0,0 +1,149 @@
+use {
+    crate::models::{
+        error::ErrorKind,
+        stats::{human_bytes, RunStats, Summary},
+    },
+    serde::Serialize,
+    std::{
+        io::{stderr, Write as ioWrite},
+        sync::{
+            mpsc::{channel as asyncQueue, RecvTimeoutError, Sender},
+            Arc,
+        },
+        thread::{Builder as thBuilder, JoinHandle},
+        time::Duration,
+    },
+};
+
+// Periodically reports the progress of a run on stderr, as a redrawn status
+// line and/or machine readable event lines. Stops (clearing the status line)
+// when dropped
+pub struct Progress {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    // expected is the total size of all inputs, if known up front
+    pub fn start(
+        stats: Arc<RunStats>,
+        expected: Option<u64>,
+        interval: Duration,
+        status_line: bool,
+        events: bool,
+    ) -> Result<Self, ErrorKind> {
+        if !status_line && !events {
+            return Ok(Progress {
+                stop: None,
+                handle: None,
+            });
+        }
+
+        let (stop_tx, stop_rx) = asyncQueue::<()>();
+        let handle = thBuilder::new().name(format!("Progress")).spawn(move || {
+            debug!("Progress reporter initialized");
+            loop {
+                let finished = match stop_rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    _ => true,
+                };
+                let event = ProgressEvent::new(&stats.summary(), expected);
+                let mut err = stderr();
+                if events {
+                    if let Ok(line) = serde_json::to_string(&event) {
+                        writeln!(err, "{}", line).ok();
+                    }
+                }
+                if status_line {
+                    match finished {
+                        // Clear the line, the run summary takes its place
+                        true => write!(err, "\r\x1b[K").ok(),
+                        false => write!(err, "\r\x1b[K{}", event).ok(),
+                    };
+                    err.flush().ok();
+                }
+
+                if finished {
+                    break;
+                }
+            }
+            debug!("Progress reporter closing");
+        })?;
+
+        Ok(Progress {
+            stop: Some(stop_tx),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+// A single progress report, serialized as one line of JSON
+#[derive(Serialize)]
+struct ProgressEvent {
+    event: &'static str,
+    elapsed_secs: f64,
+    rows_read: u64,
+    rows_written: u64,
+    bytes_read: u64,
+    bytes_total: Option<u64>,
+    rows_per_sec: f64,
+    bytes_per_sec: f64,
+    eta_secs: Option<f64>,
+}
+
+impl ProgressEvent {
+    fn new(summary: &Summary, expected: Option<u64>) -> Self {
+        let eta_secs = match (expected, summary.bytes_per_sec > 0.0) {
+            (Some(total), true) => {
+                Some(total.saturating_sub(summary.bytes_read) as f64 / summary.bytes_per_sec)
+            }
+            _ => None,
+        };
+
+        ProgressEvent {
+            event: "progress",
+            elapsed_secs: summary.elapsed_secs,
+            rows_read: summary.rows_read,
+            rows_written: summary.rows_written,
+            bytes_read: summary.bytes_read,
+            bytes_total: expected,
+            rows_per_sec: summary.rows_per_sec,
+            bytes_per_sec: summary.bytes_per_sec,
+            eta_secs,
+        }
+    }
+}
+
+impl std::fmt::Display for ProgressEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.bytes_total {
+            Some(total) if total > 0 => write!(
+                f,
+                "{} / {} ({:.1}%)",
+                human_bytes(self.bytes_read as f64),
+                human_bytes(total as f64),
+                self.bytes_read as f64 / total as f64 * 100.0
+            )?,
+            _ => write!(f, "{}", human_bytes(self.bytes_read as f64))?,
+        }
+        write!(
+            f,
+            ", {} rows, {:.0} rows/s",
+            self.rows_written, self.rows_per_sec
+        )?;
+        if let Some(eta) = self.eta_secs {
+            write!(f, ", ETA {}s", eta.ceil() as u64)?;
+        }
+
+        Ok(())
+    }
+}
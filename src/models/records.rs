@@ -0,0 +1,185 @@
+use {
+    crate::{
+        cli::ProgramArgs,
+        models::{
+            assets::InputFormat, error::ErrorKind, fixed::FixedWidth, sniff::Dialect,
+            split::Splitter, tsv::Tsv, whitespace::Whitespace,
+        },
+    },
+    csv::{ByteRecord, Position, ReaderBuilder},
+    std::{
+        boxed::Box,
+        io::{BufRead, BufReader, Error as ioError, ErrorKind as ioErrorKind, Read as ioRead},
+    },
+};
+
+// Raw records from an input, before they are validated as UTF-8.
+// Records which fail to parse carry a description of the failure
+pub type RawRecords = Box<dyn Iterator<Item = Result<ByteRecord, String>>>;
+
+// An input split into records by one of the supported readers,
+// with its header row, if it has one
+pub struct RecordSource {
+    pub headers: Option<ByteRecord>,
+    pub records: RawRecords,
+}
+
+impl RecordSource {
+    // Picks a reader for the given options
+    pub fn new(
+        opts: &ProgramArgs,
+        dialect: Dialect,
+        source: Box<dyn ioRead + Send>,
+    ) -> Result<Self, ErrorKind> {
+        match (opts.input_format(), opts.delimiter_str()) {
+            (
+                InputFormat::FixedWidth {
+                    columns,
+                    header,
+                    trim,
+                },
+                _,
+            ) => {
+                let (reader, headers) = FixedWidth::new(source, columns, *header, *trim)?;
+                Ok(RecordSource {
+                    headers: Some(headers),
+                    records: Box::new(reader),
+                })
+            }
+            (InputFormat::Whitespace { max_split, header }, _) => {
+                let (reader, headers) = Whitespace::new(source, *max_split, *header)?;
+                Ok(RecordSource {
+                    headers,
+                    records: Box::new(reader),
+                })
+            }
+            (InputFormat::Tsv { header }, _) => {
+                let (reader, headers) = Tsv::new(source, *header)?;
+                Ok(RecordSource {
+                    headers,
+                    records: Box::new(reader),
+                })
+            }
+            (InputFormat::Csv, Some(delimiter)) => Self::split(opts, dialect, delimiter, source),
+            (InputFormat::Csv, None) => Self::csv(opts, dialect, source),
+        }
+    }
+
+    fn csv(
+        opts: &ProgramArgs,
+        dialect: Dialect,
+        source: Box<dyn ioRead + Send>,
+    ) -> Result<Self, ErrorKind> {
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(dialect.delimiter)
+            .flexible(dialect.flexible)
+            .escape(dialect.escape)
+            .comment(opts.comment())
+            .quote(dialect.quote)
+            .has_headers(dialect.has_headers)
+            .trim(opts.trim_settings())
+            .double_quote(opts.quote_settings().0)
+            .quoting(opts.quote_settings().1)
+            .from_reader(source);
+
+        let headers = match dialect.has_headers {
+            true => Some(rdr.byte_headers()?.clone()),
+            false => None,
+        };
+        let records = rdr
+            .into_byte_records()
+            .map(|r| r.map_err(|e| e.to_string()));
+
+        Ok(RecordSource {
+            headers,
+            records: Box::new(records),
+        })
+    }
+
+    // Splits on a delimiter of any length, which the csv reader cannot
+    fn split(
+        opts: &ProgramArgs,
+        dialect: Dialect,
+        delimiter: &str,
+        source: Box<dyn ioRead + Send>,
+    ) -> Result<Self, ErrorKind> {
+        let mut splitter = Splitter::new(opts, dialect, delimiter, source);
+
+        let headers = match dialect.has_headers {
+            true => match splitter.next() {
+                Some(Ok(headers)) => Some(headers),
+                Some(Err(e)) => {
+                    return Err(ErrorKind::Io(ioError::new(ioErrorKind::InvalidData, e)))
+                }
+                // An empty input
+                None => Some(ByteRecord::new()),
+            },
+            false => None,
+        };
+
+        Ok(RecordSource {
+            headers,
+            records: Box::new(splitter),
+        })
+    }
+}
+
+// Reads a source line by line for the line based readers, tracking where
+// each line starts. Empty lines are skipped, if the format has no use for them
+pub struct Lines<R> {
+    source: BufReader<R>,
+    skip_empty: bool,
+    buf: Vec<u8>,
+    position: Position,
+    // (line, byte, record) of the next line
+    next: (u64, u64, u64),
+}
+
+impl<R: ioRead> Lines<R> {
+    pub fn new(source: R, skip_empty: bool) -> Self {
+        Lines {
+            source: BufReader::new(source),
+            skip_empty,
+            buf: Vec::new(),
+            position: Position::new(),
+            next: (1, 0, 0),
+        }
+    }
+
+    // The next line, without its line break
+    pub fn next_line(&mut self) -> Result<Option<&[u8]>, ioError> {
+        loop {
+            self.buf.clear();
+            let (line, byte, record) = self.next;
+            let read = self.source.read_until(b'\n', &mut self.buf)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.next.0 += 1;
+            self.next.1 += read as u64;
+
+            let end = self.line_end();
+            if end > 0 || !self.skip_empty {
+                self.next.2 += 1;
+                self.position
+                    .set_line(line)
+                    .set_byte(byte)
+                    .set_record(record);
+                return Ok(Some(&self.buf[..end]));
+            }
+        }
+    }
+
+    // Where the last line returned starts
+    pub fn position(&self) -> Position {
+        self.position.clone()
+    }
+
+    fn line_end(&self) -> usize {
+        let mut end = self.buf.len();
+        while end > 0 && (self.buf[end - 1] == b'\n' || self.buf[end - 1] == b'\r') {
+            end -= 1;
+        }
+        end
+    }
+}
@@ -0,0 +1,801 @@
+use {
+    crate::models::{
+        assets::{Compression, OutputFormat},
+        error::ErrorKind,
+    },
+    flate2::{write::GzEncoder, Compression as GzLevel},
+    fs2::FileExt,
+    serde::Deserialize,
+    serde_yaml::Value as YamlValue,
+    std::{
+        fs::{File, OpenOptions},
+        io::{
+            copy, ErrorKind as ioErrorKind, Read as ioRead, Result as ioResult, Seek, SeekFrom,
+            Stdout, Write as ioWrite,
+        },
+        path::{Path, PathBuf},
+    },
+    tempfile::NamedTempFile,
+};
+
+// Default compression levels, used when the user does not specify one
+const GZIP_DEFAULT_LEVEL: u32 = 6;
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+
+// Write destination which optionally compresses its output.
+// Compressed streams must be finished to write their trailing frames
+pub enum Encoder<W: ioWrite> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<W>),
+}
+
+impl<W: ioWrite> Encoder<W> {
+    pub fn new(
+        inner: W,
+        compression: Option<(Compression, Option<u32>)>,
+    ) -> Result<Self, ErrorKind> {
+        let encoder = match compression {
+            Some((Compression::Gzip, level)) => Encoder::Gzip(GzEncoder::new(
+                inner,
+                GzLevel::new(level.unwrap_or(GZIP_DEFAULT_LEVEL)),
+            )),
+            Some((Compression::Zstd, level)) => Encoder::Zstd(zstd::stream::write::Encoder::new(
+                inner,
+                level.map(|l| l as i32).unwrap_or(ZSTD_DEFAULT_LEVEL),
+            )?),
+            // Output compression is restricted to gzip and zstd by the CLI
+            Some(_) => unreachable!(),
+            None => Encoder::Plain(inner),
+        };
+
+        Ok(encoder)
+    }
+
+    // Completes the compressed stream (if any), returning the underlying writer
+    pub fn finish(self) -> Result<W, ErrorKind> {
+        let inner = match self {
+            Encoder::Plain(w) => w,
+            Encoder::Gzip(gz) => gz.finish()?,
+            Encoder::Zstd(zstd) => zstd.finish()?,
+        };
+
+        Ok(inner)
+    }
+}
+
+impl<W: ioWrite> ioWrite for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> ioResult<usize> {
+        match self {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gzip(gz) => gz.write(buf),
+            Encoder::Zstd(zstd) => zstd.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> ioResult<()> {
+        match self {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(gz) => gz.flush(),
+            Encoder::Zstd(zstd) => zstd.flush(),
+        }
+    }
+}
+
+// How the writer should continue the existing output it is appending to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    // Nothing to continue, write as normal
+    Fresh,
+    // Continue the trailing Json array, which may be empty
+    JsonArray { empty: bool },
+    // Continue the trailing Yaml sequence, empty if it was an
+    // empty flow sequence ([]) which has been dropped
+    YamlSequence { empty: bool },
+    // Continue the existing TSV table, under its header line
+    TsvTable,
+}
+
+// Final write destination. Output files are staged in a temporary file
+// in the same directory, which only replaces the target once committed
+pub enum Destination {
+    Stdout(Stdout),
+    Staged {
+        file: NamedTempFile,
+        target: PathBuf,
+        lock: Option<File>,
+    },
+    // Written in place, for output which must be readable as it is written
+    Direct {
+        file: File,
+        target: PathBuf,
+        lock: Option<File>,
+    },
+}
+
+impl Destination {
+    // Creates the staging file for target. If appending, the staging file is seeded
+    // with target's current contents, which must be in the shape of the given format
+    pub fn stage(
+        target: &Path,
+        append: Option<OutputFormat>,
+        lock: bool,
+    ) -> Result<(Self, Resume), ErrorKind> {
+        Self::stage_inner(target, append, lock).map_err(|e| match e {
+            ErrorKind::Io(e) => ErrorKind::UnwritableOutput(format!("{}: {}", target.display(), e)),
+            e => e,
+        })
+    }
+
+    fn stage_inner(
+        target: &Path,
+        append: Option<OutputFormat>,
+        lock: bool,
+    ) -> Result<(Self, Resume), ErrorKind> {
+        let mut resume = Resume::Fresh;
+        let lock = match lock {
+            true => Some(lock_target(target)?),
+            false => None,
+        };
+        let directory = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".aureate.").suffix(".tmp");
+        // Temporary files are owner only, create it as a regular file would
+        // be instead, with the umask applied. Existing targets keep their mode
+        #[cfg(unix)]
+        {
+            use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+            builder.permissions(Permissions::from_mode(0o666));
+        }
+        let mut file = builder.tempfile_in(directory)?;
+
+        match File::open(target) {
+            Ok(mut existing) => {
+                file.as_file()
+                    .set_permissions(existing.metadata()?.permissions())?;
+                if let Some(format) = append {
+                    resume = seed_append(&mut existing, file.as_file_mut(), format)?.ok_or_else(
+                        || {
+                            ErrorKind::UnwritableOutput(format!(
+                                "{}: existing content is not {}, refusing to append",
+                                target.display(),
+                                format.shape()
+                            ))
+                        },
+                    )?;
+                }
+            }
+            Err(ref e) if e.kind() == ioErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        debug!("Staging output in {:?}", file.path());
+
+        Ok((
+            Destination::Staged {
+                file,
+                target: target.to_path_buf(),
+                lock,
+            },
+            resume,
+        ))
+    }
+
+    // Opens target for writing in place, without staging. Existing content is
+    // either replaced, or appended to if it is in the shape of the given format:
+    // only a missing line break is added
+    pub fn direct(
+        target: &Path,
+        append: Option<OutputFormat>,
+        lock: bool,
+    ) -> Result<(Self, Resume), ErrorKind> {
+        Self::direct_inner(target, append, lock).map_err(|e| match e {
+            ErrorKind::Io(e) => ErrorKind::UnwritableOutput(format!("{}: {}", target.display(), e)),
+            e => e,
+        })
+    }
+
+    fn direct_inner(
+        target: &Path,
+        append: Option<OutputFormat>,
+        lock: bool,
+    ) -> Result<(Self, Resume), ErrorKind> {
+        let lock = match lock {
+            true => Some(lock_target(target)?),
+            false => None,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .append(append.is_some())
+            .truncate(append.is_none())
+            .open(target)?;
+
+        let length = file.metadata()?.len();
+        let resume = match (append, length) {
+            (Some(format), length) if length > 0 => {
+                if !continues(&mut file, length, format)? {
+                    return Err(ErrorKind::UnwritableOutput(format!(
+                        "{}: existing content is not {}, refusing to append",
+                        target.display(),
+                        format.shape()
+                    )));
+                }
+                let mut last = [0u8];
+                file.seek(SeekFrom::Start(length - 1))?;
+                file.read_exact(&mut last)?;
+                if last[0] != b'\n' {
+                    file.write_all(b"\n")?;
+                }
+                match format {
+                    OutputFormat::Tsv => Resume::TsvTable,
+                    _ => Resume::Fresh,
+                }
+            }
+            _ => Resume::Fresh,
+        };
+        debug!("Writing output directly to {:?}", target);
+
+        Ok((
+            Destination::Direct {
+                file,
+                target: target.to_path_buf(),
+                lock,
+            },
+            resume,
+        ))
+    }
+
+    // Moves staged output over its target, should only be called
+    // once all output has been successfully written
+    pub fn commit(self) -> Result<(), ErrorKind> {
+        match self {
+            Destination::Stdout(mut out) => out.flush()?,
+            Destination::Staged { file, target, lock } => {
+                file.as_file().sync_all()?;
+                file.persist(&target)
+                    .map_err(|e| ErrorKind::from(e.error))?;
+                info!("Output written to {:?}", target);
+                // Released only after the target has been replaced
+                drop(lock);
+            }
+            Destination::Direct { file, target, lock } => {
+                file.sync_all()?;
+                info!("Output written to {:?}", target);
+                drop(lock);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ioWrite for Destination {
+    fn write(&mut self, buf: &[u8]) -> ioResult<usize> {
+        match self {
+            Destination::Stdout(out) => out.write(buf),
+            Destination::Staged { file, .. } => file.write(buf),
+            Destination::Direct { file, .. } => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> ioResult<()> {
+        match self {
+            Destination::Stdout(out) => out.flush(),
+            Destination::Staged { file, .. } => file.flush(),
+            Destination::Direct { file, .. } => file.flush(),
+        }
+    }
+}
+
+// Takes an advisory lock on '<target>.lock', blocking until it is acquired
+fn lock_target(target: &Path) -> Result<File, ErrorKind> {
+    let mut lock_path = target.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&lock_path)?;
+
+    info!("Waiting for lock on {:?}...", lock_path);
+    lock.lock_exclusive()?;
+    debug!("Lock acquired");
+
+    Ok(lock)
+}
+
+// Copies the existing output into the staging file, such that the writer can
+// continue it. Returns None if it is not in the expected shape
+fn seed_append(
+    existing: &mut File,
+    staged: &mut File,
+    format: OutputFormat,
+) -> Result<Option<Resume>, ErrorKind> {
+    let length = existing.metadata()?.len();
+    let (first, (end, last)) = match (
+        first_non_whitespace(existing)?,
+        last_non_whitespace(existing, length)?,
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        // Empty, nothing to continue
+        _ => return Ok(Some(Resume::Fresh)),
+    };
+
+    let resume = match format {
+        // Everything up to the trailing array's closing bracket is kept
+        OutputFormat::Json | OutputFormat::JsonPretty => match (first, last) {
+            (b'[', b']') => {
+                // The opening bracket guarantees there is something before the closing one
+                let (before, previous) = last_non_whitespace(existing, end)?.unwrap();
+                copy_prefix(existing, staged, before + 1)?;
+                Resume::JsonArray {
+                    empty: previous == b'[',
+                }
+            }
+            _ => return Ok(None),
+        },
+        OutputFormat::NdJson => match (first, last) {
+            (b'{', b'}') => {
+                copy_prefix(existing, staged, end + 1)?;
+                staged.write_all(b"\n")?;
+                Resume::Fresh
+            }
+            _ => return Ok(None),
+        },
+        // Yaml is checked by parsing, the writer then continues the last document
+        OutputFormat::Yaml => {
+            let mut content = Vec::new();
+            existing.seek(SeekFrom::Start(0))?;
+            existing.read_to_end(&mut content)?;
+            // The length of the last document, if every document
+            // is valid and the last is a sequence
+            let last_sequence = |content: &[u8]| {
+                let mut last_document = None;
+                for document in serde_yaml::Deserializer::from_slice(content) {
+                    match YamlValue::deserialize(document) {
+                        Ok(value) => last_document = Some(value),
+                        // Documents keep coming after an error, never ending
+                        Err(_) => return None,
+                    }
+                }
+                match last_document {
+                    Some(YamlValue::Sequence(seq)) => Some(seq.len()),
+                    _ => None,
+                }
+            };
+            let resume = match (last_sequence(&content), last) {
+                // An empty flow sequence cannot be extended, so it is dropped
+                // and a block sequence written in its place
+                (Some(0), b']') => {
+                    match last_non_whitespace(existing, end)? {
+                        Some((before, b'[')) => copy_prefix(existing, staged, before)?,
+                        _ => return Ok(None),
+                    }
+                    return Ok(Some(Resume::YamlSequence { empty: true }));
+                }
+                // Either a flow sequence, or a block sequence whose last item is one.
+                // Only the latter can be continued with another item
+                (Some(items), b']') => {
+                    content.extend_from_slice(b"\n- ~\n");
+                    match last_sequence(&content) == Some(items + 1) {
+                        true => Resume::YamlSequence { empty: false },
+                        false => return Ok(None),
+                    }
+                }
+                (Some(_), _) => Resume::YamlSequence { empty: false },
+                _ => return Ok(None),
+            };
+            copy_prefix(existing, staged, end + 1)?;
+            staged.write_all(b"\n")?;
+            resume
+        }
+        // Any content is a table, trailing tabs may be empty fields so all of it is kept
+        OutputFormat::Tsv => {
+            copy_prefix(existing, staged, length)?;
+            let mut last = [0u8];
+            existing.seek(SeekFrom::Start(length - 1))?;
+            existing.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                staged.write_all(b"\n")?;
+            }
+            Resume::TsvTable
+        }
+    };
+
+    Ok(Some(resume))
+}
+
+// Whether existing output written in place can be continued by appending rows,
+// by the same checks as seed_append. Only line based formats can be
+fn continues(existing: &mut File, length: u64, format: OutputFormat) -> Result<bool, ErrorKind> {
+    let fits = match format {
+        OutputFormat::NdJson => match (
+            first_non_whitespace(existing)?,
+            last_non_whitespace(existing, length)?,
+        ) {
+            (Some(b'{'), Some((_, b'}'))) | (None, None) => true,
+            _ => false,
+        },
+        OutputFormat::Tsv => true,
+        _ => false,
+    };
+
+    Ok(fits)
+}
+
+fn copy_prefix(from: &mut File, to: &mut File, length: u64) -> Result<(), ErrorKind> {
+    from.seek(SeekFrom::Start(0))?;
+    copy(&mut from.take(length), to)?;
+
+    Ok(())
+}
+
+fn first_non_whitespace(file: &mut File) -> Result<Option<u8>, ErrorKind> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut buffer = [0u8; 4096];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(None),
+            n => {
+                if let Some(b) = buffer[..n].iter().find(|b| !b.is_ascii_whitespace()) {
+                    return Ok(Some(*b));
+                }
+            }
+        }
+    }
+}
+
+// Scans backwards from (but excluding) offset, returning the offset and value
+// of the last non-whitespace byte
+fn last_non_whitespace(file: &mut File, offset: u64) -> Result<Option<(u64, u8)>, ErrorKind> {
+    let mut buffer = [0u8; 4096];
+    let mut end = offset;
+    while end > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(i) = chunk.iter().rposition(|b| !b.is_ascii_whitespace()) {
+            return Ok(Some((start + i as u64, chunk[i])));
+        }
+        end = start;
+    }
+
+    Ok(None)
+}
+
+// Writer adapter for continuing an existing Json array. The opening '[' of a
+// freshly serialized array is dropped, and if the existing array had elements
+// a ',' is inserted before the first new one
+pub struct ContinueArray<W: ioWrite> {
+    inner: W,
+    opened: bool,
+    separate: bool,
+}
+
+impl<W: ioWrite> ContinueArray<W> {
+    pub fn new(inner: W, separate: bool) -> Self {
+        ContinueArray {
+            inner,
+            opened: false,
+            separate,
+        }
+    }
+}
+
+impl<W: ioWrite> ioWrite for ContinueArray<W> {
+    fn write(&mut self, buf: &[u8]) -> ioResult<usize> {
+        match (self.opened, buf.first()) {
+            (_, None) => Ok(0),
+            (false, Some(b'[')) => {
+                self.opened = true;
+                Ok(1)
+            }
+            (_, Some(b)) => {
+                // An immediate ']' means no new elements, so no separator
+                if self.separate && *b != b']' {
+                    self.inner.write_all(b",")?;
+                }
+                self.opened = true;
+                self.separate = false;
+                self.inner.write(buf)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> ioResult<()> {
+        self.inner.flush()
+    }
+}
+
+// Writer adapter which drops a leading prefix, if the first bytes written match it
+pub struct StripPrefix<W: ioWrite> {
+    inner: W,
+    prefix: &'static [u8],
+    matched: usize,
+}
+
+impl<W: ioWrite> StripPrefix<W> {
+    pub fn new(inner: W, prefix: &'static [u8]) -> Self {
+        StripPrefix {
+            inner,
+            prefix,
+            matched: 0,
+        }
+    }
+}
+
+impl<W: ioWrite> ioWrite for StripPrefix<W> {
+    fn write(&mut self, buf: &[u8]) -> ioResult<usize> {
+        if self.matched == self.prefix.len() {
+            return self.inner.write(buf);
+        }
+
+        let remaining = &self.prefix[self.matched..];
+        let overlap = buf.len().min(remaining.len());
+        if buf[..overlap] == remaining[..overlap] {
+            self.matched += overlap;
+            Ok(overlap)
+        } else {
+            // Not the expected prefix, give back what was withheld and stop matching
+            self.inner.write_all(&self.prefix[..self.matched])?;
+            self.matched = self.prefix.len();
+            self.inner.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> ioResult<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json, serde_json::Value as JsonValue};
+
+    // Seeds a staging file from existing output, returning how the writer
+    // should resume it alongside what was staged
+    fn seed(existing: &str, format: OutputFormat) -> Option<(Resume, String)> {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(existing.as_bytes()).unwrap();
+        let mut staged = tempfile::tempfile().unwrap();
+        let resume = seed_append(&mut file, &mut staged, format).unwrap()?;
+
+        let mut seeded = String::new();
+        staged.seek(SeekFrom::Start(0)).unwrap();
+        staged.read_to_string(&mut seeded).unwrap();
+        Some((resume, seeded))
+    }
+
+    // Continues seeded output with rows, as the writer would
+    fn continued(seeded: String, resume: Resume, format: OutputFormat, rows: &JsonValue) -> String {
+        let mut out = seeded.into_bytes();
+        let empty = rows.as_array().unwrap().is_empty();
+        match (format, resume) {
+            (OutputFormat::Json, Resume::JsonArray { empty }) => {
+                serde_json::to_writer(ContinueArray::new(&mut out, !empty), rows).unwrap()
+            }
+            (OutputFormat::JsonPretty, Resume::JsonArray { empty }) => {
+                serde_json::to_writer_pretty(ContinueArray::new(&mut out, !empty), rows).unwrap()
+            }
+            (OutputFormat::Yaml, Resume::YamlSequence { empty: true }) if empty => {
+                out.extend_from_slice(b"[]\n")
+            }
+            (OutputFormat::Yaml, Resume::YamlSequence { .. }) if empty => (),
+            (OutputFormat::Yaml, Resume::YamlSequence { .. }) => {
+                serde_yaml::to_writer(StripPrefix::new(&mut out, b"---\n"), rows).unwrap()
+            }
+            _ => unreachable!(),
+        }
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn seed_append_shapes() {
+        use OutputFormat::*;
+        // (format, existing output, how it is resumed and what is staged)
+        let cases: [(OutputFormat, &str, Option<(Resume, &str)>); 22] = [
+            (Json, "", Some((Resume::Fresh, ""))),
+            (Json, " \n", Some((Resume::Fresh, ""))),
+            (Json, "[]", Some((Resume::JsonArray { empty: true }, "["))),
+            (
+                Json,
+                "[ ]\n",
+                Some((Resume::JsonArray { empty: true }, "[")),
+            ),
+            (
+                Json,
+                "[{\"a\":1}]\n",
+                Some((Resume::JsonArray { empty: false }, "[{\"a\":1}")),
+            ),
+            (
+                JsonPretty,
+                "[\n  {\n    \"a\": 1\n  }\n]",
+                Some((
+                    Resume::JsonArray { empty: false },
+                    "[\n  {\n    \"a\": 1\n  }",
+                )),
+            ),
+            (Json, "{\"a\":1}", None),
+            (Json, "[{\"a\":1}] x", None),
+            (
+                NdJson,
+                "{\"a\":1}\n{\"a\":2}",
+                Some((Resume::Fresh, "{\"a\":1}\n{\"a\":2}\n")),
+            ),
+            (NdJson, "[{\"a\":1}]\n", None),
+            (
+                Yaml,
+                "---\n- a: 1\n",
+                Some((Resume::YamlSequence { empty: false }, "---\n- a: 1\n")),
+            ),
+            // Empty flow sequences are dropped
+            (
+                Yaml,
+                "[]\n",
+                Some((Resume::YamlSequence { empty: true }, "")),
+            ),
+            (
+                Yaml,
+                "---\n[]\n",
+                Some((Resume::YamlSequence { empty: true }, "---\n")),
+            ),
+            (Yaml, "[a, b]\n", None),
+            (
+                Yaml,
+                "---\n- [a, b]\n",
+                Some((Resume::YamlSequence { empty: false }, "---\n- [a, b]\n")),
+            ),
+            // Only the last document is continued
+            (
+                Yaml,
+                "---\na: 1\n---\n- b: 2\n",
+                Some((
+                    Resume::YamlSequence { empty: false },
+                    "---\na: 1\n---\n- b: 2\n",
+                )),
+            ),
+            (
+                Yaml,
+                "---\n- a: 1\n---\n[]\n",
+                Some((Resume::YamlSequence { empty: true }, "---\n- a: 1\n---\n")),
+            ),
+            (Yaml, "---\n- a: 1\n---\nb: 2\n", None),
+            (Yaml, "a: 1\n", None),
+            (Yaml, "- a: [\n", None),
+            (Tsv, "a\tb\n1\t2", Some((Resume::TsvTable, "a\tb\n1\t2\n"))),
+            (Tsv, "a\tb\n1\t\n", Some((Resume::TsvTable, "a\tb\n1\t\n"))),
+        ];
+
+        for (format, existing, expected) in cases.iter() {
+            let expected = expected.map(|(resume, staged)| (resume, staged.to_string()));
+            assert_eq!(seed(existing, *format), expected, "{:?}", existing);
+        }
+    }
+
+    #[test]
+    fn appended_output_parses() {
+        use OutputFormat::*;
+        let row = json!([{"b": 2}]);
+        let none = json!([]);
+        // (format, existing output, rows appended, every document afterwards)
+        let cases = [
+            (Json, "[]", &row, vec![json!([{"b": 2}])]),
+            (Json, "[{\"a\":1}]", &row, vec![json!([{"a": 1}, {"b": 2}])]),
+            (Json, "[{\"a\":1}]", &none, vec![json!([{"a": 1}])]),
+            (Json, "[]", &none, vec![json!([])]),
+            (
+                JsonPretty,
+                "[\n  {\n    \"a\": 1\n  }\n]",
+                &row,
+                vec![json!([{"a": 1}, {"b": 2}])],
+            ),
+            (JsonPretty, "[]", &row, vec![json!([{"b": 2}])]),
+            (
+                Yaml,
+                "---\n- a: 1\n",
+                &row,
+                vec![json!([{"a": 1}, {"b": 2}])],
+            ),
+            (Yaml, "---\n- a: 1\n", &none, vec![json!([{"a": 1}])]),
+            (Yaml, "[]\n", &row, vec![json!([{"b": 2}])]),
+            (Yaml, "---\n[]\n", &none, vec![json!([])]),
+            (
+                Yaml,
+                "---\na: 1\n---\n- a: 1\n",
+                &row,
+                vec![json!({"a": 1}), json!([{"a": 1}, {"b": 2}])],
+            ),
+        ];
+
+        for (format, existing, rows, expected) in cases.iter() {
+            let (resume, seeded) = seed(existing, *format).unwrap();
+            let output = continued(seeded, resume, *format, rows);
+            let documents = match format {
+                Yaml => serde_yaml::Deserializer::from_str(&output)
+                    .map(|document| JsonValue::deserialize(document).unwrap())
+                    .collect::<Vec<_>>(),
+                _ => vec![serde_json::from_str::<JsonValue>(&output).unwrap()],
+            };
+            assert_eq!(&documents, expected, "{:?} then {}", existing, output);
+        }
+    }
+
+    #[test]
+    fn direct_append_shapes() {
+        use OutputFormat::*;
+        // (format, existing output, how it is resumed, if it can be appended to)
+        let cases = [
+            (NdJson, "", Some(Resume::Fresh)),
+            (NdJson, "{\"a\":1}\n", Some(Resume::Fresh)),
+            (NdJson, "{\"a\":1}", Some(Resume::Fresh)),
+            (NdJson, " \n", Some(Resume::Fresh)),
+            (NdJson, "[{\"a\":1}]\n", None),
+            (NdJson, "[\n  {\n    \"a\": 1\n  }\n]", None),
+            (NdJson, "- a: 1\n", None),
+            (Tsv, "", Some(Resume::Fresh)),
+            (Tsv, "a\tb\n1\t2\n", Some(Resume::TsvTable)),
+            (Tsv, "[{\"a\":1}]\n", Some(Resume::TsvTable)),
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("out");
+
+        for (format, existing, expected) in cases.iter() {
+            std::fs::write(&target, existing).unwrap();
+            let resume = Destination::direct(&target, Some(*format), false)
+                .ok()
+                .map(|(_, resume)| resume);
+            assert_eq!(resume, *expected, "{:?}", existing);
+
+            // Refused output is left as it was
+            let content = std::fs::read_to_string(&target).unwrap();
+            match expected {
+                Some(_) if !existing.is_empty() && !existing.ends_with('\n') => {
+                    assert_eq!(content, format!("{}\n", existing))
+                }
+                _ => assert_eq!(content, *existing),
+            }
+        }
+    }
+
+    #[test]
+    fn writer_adapters() {
+        // (writes, output) of continuing an array with elements
+        let arrays: [(&[&str], &str); 4] = [
+            (&["[", "1", ",", "2", "]"], ",1,2]"),
+            (&["[1,2]"], ",1,2]"),
+            (&["[", "]"], "]"),
+            (&["[]"], "]"),
+        ];
+        for (writes, expected) in arrays.iter() {
+            let mut out = Vec::new();
+            let mut writer = ContinueArray::new(&mut out, true);
+            for write in writes.iter() {
+                writer.write_all(write.as_bytes()).unwrap();
+            }
+            assert_eq!(String::from_utf8(out).unwrap(), *expected, "{:?}", writes);
+        }
+
+        // (writes, output) of stripping a document start
+        let prefixes: [(&[&str], &str); 6] = [
+            (&["---\n- a\n"], "- a\n"),
+            (&["--", "-", "\n- a\n"], "- a\n"),
+            (&["---\n"], ""),
+            (&["- a\n"], "- a\n"),
+            (&["--", "x"], "--x"),
+            (&["---\n", "---\n"], "---\n"),
+        ];
+        for (writes, expected) in prefixes.iter() {
+            let mut out = Vec::new();
+            let mut writer = StripPrefix::new(&mut out, b"---\n");
+            for write in writes.iter() {
+                writer.write_all(write.as_bytes()).unwrap();
+            }
+            assert_eq!(String::from_utf8(out).unwrap(), *expected, "{:?}", writes);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use {
+    crate::cli::ProgramArgs,
+    csv::{ReaderBuilder, StringRecord},
+    std::fmt,
+};
+
+// Number of bytes read from the front of an input to guess its dialect from
+pub const SNIFF_BYTES: usize = 8192;
+
+// Delimiters tried when sniffing, in order of preference on a tie
+const DELIMITERS: [u8; 5] = [b',', b';', b'\t', b'|', b':'];
+
+// Number of rows (after the first) compared against the first, when guessing
+// whether the input has a header row
+const HEADER_SAMPLE_ROWS: usize = 20;
+
+// The options needed to parse an input
+#[derive(Debug, Clone, Copy)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    // None => quotes are escaped by doubling them
+    pub escape: Option<u8>,
+    pub has_headers: bool,
+    pub flexible: bool,
+}
+
+impl Dialect {
+    // The dialect described by the user's options
+    pub fn from_opts(opts: &ProgramArgs) -> Self {
+        Dialect {
+            delimiter: opts.delimiter(),
+            quote: opts.quote(),
+            escape: opts.escape(),
+            has_headers: true,
+            flexible: opts.flexible(),
+        }
+    }
+
+    // Replaces any option the user did not explicitly set (directly, or
+    // through a dialect preset) with its sniffed value
+    pub fn merge(self, sniffed: Dialect, opts: &ProgramArgs) -> Self {
+        let explicit = |name: &str| opts.is_explicit(name) || opts.is_explicit("csv.dialect_csv");
+        let pick = |name: &str, given, guessed| match explicit(name) {
+            true => given,
+            false => guessed,
+        };
+
+        Dialect {
+            delimiter: pick("csv.delimiter_csv", self.delimiter, sniffed.delimiter),
+            quote: pick("csv.quote_csv", self.quote, sniffed.quote),
+            escape: match explicit("csv.escape_csv") {
+                true => self.escape,
+                false => sniffed.escape,
+            },
+            has_headers: sniffed.has_headers,
+            flexible: self.flexible || sniffed.flexible,
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "delimiter {:?}, quote {:?}, escape {}, header row: {}, ragged rows: {}",
+            self.delimiter as char,
+            self.quote as char,
+            self.escape
+                .map_or(format!("doubled quotes"), |e| format!("{:?}", e as char)),
+            self.has_headers,
+            self.flexible
+        )
+    }
+}
+
+// Guesses the dialect of a sample taken from the front of an input
+pub fn sniff(sample: &[u8]) -> Dialect {
+    // Drop any partial line at the end of the sample
+    let sample = match (
+        sample.len() == SNIFF_BYTES,
+        sample.iter().rposition(|&b| b == b'\n'),
+    ) {
+        (true, Some(end)) => &sample[..=end],
+        _ => sample,
+    };
+
+    let quote = sniff_quote(sample);
+    let escape = sniff_escape(sample, quote);
+
+    // The delimiter giving the most consistent field counts, then the most fields
+    let (delimiter, rows) = DELIMITERS
+        .iter()
+        .map(|&delimiter| (delimiter, parse_sample(sample, delimiter, quote, escape)))
+        .max_by(|(da, a), (db, b)| {
+            let (ca, fa) = consistency(a);
+            let (cb, fb) = consistency(b);
+            ca.partial_cmp(&cb)
+                .unwrap()
+                .then(fa.cmp(&fb))
+                // Prefer the earlier delimiter on a tie
+                .then(position(*db).cmp(&position(*da)))
+        })
+        .unwrap();
+
+    let flexible = rows.windows(2).any(|w| w[0].len() != w[1].len());
+
+    Dialect {
+        delimiter,
+        quote,
+        escape,
+        has_headers: sniff_headers(&rows),
+        flexible,
+    }
+}
+
+fn position(delimiter: u8) -> usize {
+    DELIMITERS.iter().position(|&d| d == delimiter).unwrap()
+}
+
+// Double quotes, unless single quotes are more often seen wrapping fields
+fn sniff_quote(sample: &[u8]) -> u8 {
+    let wrapping = |quote: u8| {
+        sample
+            .windows(2)
+            .filter(|w| {
+                (w[0] == quote && (DELIMITERS.contains(&w[1]) || w[1] == b'\n' || w[1] == b'\r'))
+                    || (w[1] == quote && (DELIMITERS.contains(&w[0]) || w[0] == b'\n'))
+            })
+            .count()
+    };
+
+    match wrapping(b'\'') > wrapping(b'"') {
+        true => b'\'',
+        false => b'"',
+    }
+}
+
+// Backslash escapes, if they are seen before quotes and doubled quotes are not
+fn sniff_escape(sample: &[u8], quote: u8) -> Option<u8> {
+    let escaped = sample
+        .windows(2)
+        .filter(|w| w[0] == b'\\' && w[1] == quote)
+        .count();
+    let doubled = sample
+        .windows(3)
+        // Not an empty quoted field, or an escaped quote closing a field
+        .filter(|w| {
+            w[0] != quote
+                && w[0] != b'\\'
+                && !DELIMITERS.contains(&w[0])
+                && w[0] != b'\n'
+                && w[1..] == [quote, quote]
+        })
+        .count();
+
+    match escaped > 0 && doubled == 0 {
+        true => Some(b'\\'),
+        false => None,
+    }
+}
+
+fn parse_sample(sample: &[u8], delimiter: u8, quote: u8, escape: Option<u8>) -> Vec<StringRecord> {
+    ReaderBuilder::new()
+        .delimiter(delimiter)
+        .quote(quote)
+        .escape(escape)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(sample)
+        .records()
+        .filter_map(|r| r.ok())
+        .collect()
+}
+
+// The share of rows with the most common field count, and that field count.
+// Delimiters which never split a row score nothing
+fn consistency(rows: &[StringRecord]) -> (f64, usize) {
+    let mut counts = std::collections::BTreeMap::new();
+    for row in rows {
+        *counts.entry(row.len()).or_insert(0usize) += 1;
+    }
+
+    match counts
+        .into_iter()
+        .max_by_key(|&(fields, rows)| (rows, fields))
+    {
+        Some((fields, count)) if fields > 1 => (count as f64 / rows.len() as f64, fields),
+        _ => (0.0, 1),
+    }
+}
+
+// Votes per column on whether the first row looks different from the rest:
+// a text cell above numbers, or a cell of a different length than the
+// fixed length cells below it
+fn sniff_headers(rows: &[StringRecord]) -> bool {
+    let (first, rest) = match rows.split_first() {
+        Some((first, rest)) if !rest.is_empty() => (first, rest),
+        // Nothing to compare against, assume the usual
+        _ => return true,
+    };
+    let rest = &rest[..rest.len().min(HEADER_SAMPLE_ROWS)];
+
+    let is_numeric = |s: &str| s.trim().parse::<f64>().is_ok();
+    let votes: i64 = first
+        .iter()
+        .enumerate()
+        .map(|(column, header)| {
+            let cells = rest
+                .iter()
+                .filter_map(|r| r.get(column))
+                .collect::<Vec<&str>>();
+            if cells.is_empty() {
+                return 0;
+            }
+
+            let numeric = cells.iter().all(|c| is_numeric(c));
+            let length = cells[0].len();
+            let fixed_length = cells.iter().all(|c| c.len() == length);
+            match (numeric, fixed_length) {
+                (true, _) if !is_numeric(header) => 1,
+                (true, _) => -1,
+                (false, true) if header.len() != length => 1,
+                (false, true) => -1,
+                (false, false) => 0,
+            }
+        })
+        .sum();
+
+    votes >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimiter_ties() {
+        // (sample, expected delimiter)
+        let cases: [(&[u8], u8); 7] = [
+            // Equally consistent, with as many fields, the earlier delimiter wins
+            (b"a,b;c\n1,2;3\n", b','),
+            (b"a;b|c\n1;2|3\n", b';'),
+            (b"a\tb:c\n1\t2:3\n", b'\t'),
+            (b"a|b:c\n1|2:3\n", b'|'),
+            // No delimiter splits a row, so none is better than the default
+            (b"abc\ndef\n", b','),
+            // More fields beat an earlier delimiter
+            (b"a;b;c,d\n1;2;3,4\n", b';'),
+            // Consistency beats more fields
+            (b"a,b|c|d\n1,2|3\n4,5|6|7|8\n", b','),
+        ];
+
+        for (sample, expected) in cases.iter() {
+            let sniffed = sniff(sample).delimiter;
+            assert_eq!(
+                sniffed as char,
+                *expected as char,
+                "{:?}",
+                String::from_utf8_lossy(sample)
+            );
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use {
+    crate::{
+        cli::ProgramArgs,
+        match_with_log,
+        models::{assets::Compression, error::ErrorKind},
+    },
+    bzip2::read::MultiBzDecoder,
+    encoding_rs::{Encoding, UTF_8},
+    encoding_rs_io::DecodeReaderBytesBuilder,
+    flate2::read::MultiGzDecoder,
+    std::{
+        boxed::Box,
+        io::{Chain, Cursor, Error as ioError, Read as ioRead},
+    },
+    xz2::read::XzDecoder,
+};
+
+// Reads up to n bytes from the front of a source, returning them alongside
+// a reader which replays them before continuing with the rest of the source
+pub fn peek<R>(mut source: R, n: usize) -> Result<(Vec<u8>, Chain<Cursor<Vec<u8>>, R>), ioError>
+where
+    R: ioRead,
+{
+    let mut head = Vec::with_capacity(n);
+    (&mut source).take(n as u64).read_to_end(&mut head)?;
+
+    Ok((head.clone(), Cursor::new(head).chain(source)))
+}
+
+// Transparently decompresses a read source, if its leading
+// magic bytes identify it as a supported compression format
+pub(crate) fn decompress_source(
+    source: Box<dyn ioRead + Send>,
+) -> Result<Box<dyn ioRead + Send>, ErrorKind> {
+    let (head, source) = peek(source, 10)?;
+
+    let decompressed: Box<dyn ioRead + Send> = match Compression::from_magic(&head) {
+        Some(format) => match_with_log!(
+            match format {
+                Compression::Gzip => Box::new(MultiGzDecoder::new(source)),
+                Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(source)?),
+                Compression::Bzip2 => Box::new(MultiBzDecoder::new(source)),
+                Compression::Xz => Box::new(XzDecoder::new_multi_decoder(source)),
+            },
+            info!("Detected {} compressed input, decompressing...", format)
+        ),
+        None => Box::new(source),
+    };
+
+    Ok(decompressed)
+}
+
+// Transcodes a read source into UTF-8, if required. A byte order mark takes
+// precedence over the user provided encoding, and no BOM + no encoding means
+// the source is assumed to already be UTF-8. Any BOM found is stripped
+pub(crate) fn decode_source(
+    opts: &ProgramArgs,
+    source: Box<dyn ioRead + Send>,
+) -> Result<Box<dyn ioRead + Send>, ErrorKind> {
+    let (head, mut source) = peek(source, 3)?;
+    let bom = Encoding::for_bom(&head);
+
+    if let Some((bom, _)) = bom {
+        info!("Detected {} byte order mark, stripping...", bom.name());
+    }
+
+    let encoding: &'static Encoding = match (bom, opts.encoding()) {
+        (Some((bom, _)), Some(label)) if bom != label => match_with_log!(
+            bom,
+            warn!(
+                "Byte order mark indicates {}, ignoring requested encoding {}",
+                bom.name(),
+                label.name()
+            )
+        ),
+        (Some((bom, _)), _) => bom,
+        (None, Some(label)) => label,
+        (None, None) => UTF_8,
+    };
+
+    if encoding == UTF_8 {
+        // Skip past the BOM (if any) in the replayed bytes
+        if let Some((_, bom_length)) = bom {
+            source.get_mut().0.set_position(bom_length as u64);
+        }
+        return Ok(Box::new(source));
+    }
+
+    // The decoder handles stripping any BOM itself
+    info!("Transcoding input from {} to UTF-8...", encoding.name());
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(source);
+
+    Ok(Box::new(decoder))
+}
@@ -0,0 +1,284 @@
+use {
+    crate::{cli::ProgramArgs, models::sniff::Dialect},
+    csv::{ByteRecord, Position, Trim},
+    std::io::{BufRead, BufReader, Read as ioRead},
+};
+
+// Splits a source into records on a delimiter of any length. Otherwise
+// follows the csv reader: quoted fields may contain delimiters and line
+// breaks, empty lines are skipped and a header row, if any, is the first record
+pub struct Splitter<R> {
+    source: BufReader<R>,
+    delimiter: Vec<u8>,
+    quote: u8,
+    escape: Option<u8>,
+    double_quote: bool,
+    quoting: bool,
+    comment: Option<u8>,
+    trim: Trim,
+    has_headers: bool,
+    flexible: bool,
+    // The raw bytes of the record being read
+    buf: Vec<u8>,
+    // (line, byte, record) of the next record
+    line: u64,
+    byte: u64,
+    record: u64,
+    // Field count of the first record, rows must match unless flexible
+    fields: Option<usize>,
+    done: bool,
+}
+
+impl<R: ioRead> Splitter<R> {
+    pub fn new(opts: &ProgramArgs, dialect: Dialect, delimiter: &str, source: R) -> Self {
+        let (double_quote, quoting) = opts.quote_settings();
+        Splitter {
+            source: BufReader::new(source),
+            delimiter: delimiter.as_bytes().to_vec(),
+            quote: dialect.quote,
+            escape: dialect.escape,
+            double_quote,
+            quoting,
+            comment: opts.comment(),
+            trim: opts.trim_settings(),
+            has_headers: dialect.has_headers,
+            flexible: dialect.flexible,
+            buf: Vec::new(),
+            line: 1,
+            byte: 0,
+            record: 0,
+            fields: None,
+            done: false,
+        }
+    }
+
+    // Splits the buffered bytes into fields, or returns None
+    // if they end inside a quoted field
+    fn split(&self) -> Option<ByteRecord> {
+        let mut end = self.buf.len();
+        if self.buf[..end].ends_with(b"\n") {
+            end -= 1;
+        }
+        if self.buf[..end].ends_with(b"\r") {
+            end -= 1;
+        }
+        let line = &self.buf[..end];
+
+        let mut record = ByteRecord::new();
+        let mut field = Vec::new();
+        let (mut in_quotes, mut at_start) = (false, true);
+        let mut i = 0;
+        while i < line.len() {
+            let b = line[i];
+            if in_quotes {
+                match (Some(b) == self.escape, b == self.quote) {
+                    (true, _) if i + 1 < line.len() => {
+                        field.push(line[i + 1]);
+                        i += 2;
+                    }
+                    (_, true) if self.double_quote && line.get(i + 1) == Some(&self.quote) => {
+                        field.push(self.quote);
+                        i += 2;
+                    }
+                    (_, true) => {
+                        in_quotes = false;
+                        i += 1;
+                    }
+                    _ => {
+                        field.push(b);
+                        i += 1;
+                    }
+                }
+            } else if line[i..].starts_with(&self.delimiter) {
+                record.push_field(&field);
+                field.clear();
+                at_start = true;
+                i += self.delimiter.len();
+            } else if self.quoting && at_start && b == self.quote {
+                in_quotes = true;
+                at_start = false;
+                i += 1;
+            } else {
+                field.push(b);
+                at_start = false;
+                i += 1;
+            }
+        }
+
+        match in_quotes {
+            // The line break belongs to the field, keep reading
+            true => None,
+            false => {
+                record.push_field(&field);
+                Some(record)
+            }
+        }
+    }
+
+    fn trims(&self) -> bool {
+        let is_header = self.has_headers && self.record == 0;
+        match (self.trim, is_header) {
+            (Trim::All, _) => true,
+            (Trim::Headers, true) => true,
+            (Trim::Fields, false) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<R: ioRead> Iterator for Splitter<R> {
+    type Item = Result<ByteRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'records: while !self.done {
+            let (line, byte) = (self.line, self.byte);
+            self.buf.clear();
+
+            let mut record = loop {
+                let read = match self.source.read_until(b'\n', &mut self.buf) {
+                    Ok(0) if self.buf.is_empty() => {
+                        self.done = true;
+                        return None;
+                    }
+                    Ok(read) => read,
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(e.to_string()));
+                    }
+                };
+                self.done = read == 0;
+                self.line += 1;
+                self.byte += read as u64;
+
+                // Skip empty lines and comments
+                if self.line == line + 1 {
+                    let blank = self.buf.iter().all(|&b| b == b'\n' || b == b'\r');
+                    let comment =
+                        self.comment.is_some() && self.buf.first() == self.comment.as_ref();
+                    if blank || comment {
+                        continue 'records;
+                    }
+                }
+
+                match (self.split(), self.done) {
+                    (Some(record), _) => break record,
+                    (None, true) => {
+                        return Some(Err(format!("line {}: quoted field is never closed", line)))
+                    }
+                    (None, false) => continue,
+                }
+            };
+
+            if self.trims() {
+                record.trim();
+            }
+            let mut position = Position::new();
+            position
+                .set_line(line)
+                .set_byte(byte)
+                .set_record(self.record);
+            record.set_position(Some(position));
+            self.record += 1;
+
+            let expected = *self.fields.get_or_insert(record.len());
+            return match self.flexible || record.len() == expected {
+                true => Some(Ok(record)),
+                false => Some(Err(format!(
+                    "line {}: found record with {} fields, but the previous record has {} fields",
+                    line,
+                    record.len(),
+                    expected
+                ))),
+            };
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns a single byte per read, so that every delimiter
+    // is split across the splitter's buffer refills
+    struct OneByte<'a>(&'a [u8]);
+
+    impl<'a> ioRead for OneByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buf.is_empty()) {
+                (Some((&b, rest)), false) => {
+                    buf[0] = b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn splitter<R: ioRead>(delimiter: &str, source: R) -> Splitter<R> {
+        Splitter {
+            source: BufReader::new(source),
+            delimiter: delimiter.as_bytes().to_vec(),
+            quote: b'"',
+            escape: None,
+            double_quote: true,
+            quoting: true,
+            comment: None,
+            trim: Trim::None,
+            has_headers: false,
+            flexible: true,
+            buf: Vec::new(),
+            line: 1,
+            byte: 0,
+            record: 0,
+            fields: None,
+            done: false,
+        }
+    }
+
+    fn records<R: ioRead>(splitter: Splitter<R>) -> Vec<Vec<String>> {
+        splitter
+            .map(|r| {
+                r.unwrap()
+                    .iter()
+                    .map(|f| String::from_utf8_lossy(f).to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn delimiter_across_reads() {
+        // (input, delimiter, expected records)
+        let cases: [(&str, &str, &[&[&str]]); 7] = [
+            (
+                "a~|~b~|~c\n1~|~2~|~3\n",
+                "~|~",
+                &[&["a", "b", "c"], &["1", "2", "3"]],
+            ),
+            ("a::b\r\n::\n", "::", &[&["a", "b"], &["", ""]]),
+            // Partial delimiters are part of the field
+            ("a~|b~|~c~\n", "~|~", &[&["a~|b", "c~"]]),
+            ("a~~|~b\n", "~|~", &[&["a~", "b"]]),
+            // Quoted delimiters and line breaks
+            ("\"x~|~y\"~|~z\n", "~|~", &[&["x~|~y", "z"]]),
+            ("\"multi\nline\"~|~2\n", "~|~", &[&["multi\nline", "2"]]),
+            // Multi-byte characters in the delimiter
+            ("a→→b→→\n", "→→", &[&["a", "b", ""]]),
+        ];
+
+        for (input, delimiter, expected) in cases.iter() {
+            let expected = expected
+                .iter()
+                .map(|r| r.iter().map(|f| f.to_string()).collect::<Vec<String>>())
+                .collect::<Vec<_>>();
+            let whole = records(splitter(delimiter, input.as_bytes()));
+            let split = records(splitter(delimiter, OneByte(input.as_bytes())));
+
+            assert_eq!(whole, expected, "{:?}", input);
+            assert_eq!(split, expected, "{:?} read a byte at a time", input);
+        }
+    }
+}
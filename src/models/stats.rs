@@ -0,0 +1,240 @@
+use {
+    crate::models::error::ErrorKind,
+    serde::Serialize,
+    std::{
+        fmt,
+        fs::File,
+        io::{BufWriter, Read as ioRead, Result as ioResult},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+// Counters for a single input, shared between the threads handling it
+#[derive(Debug)]
+pub struct InputStats {
+    name: String,
+    rows_read: AtomicU64,
+    rows_rejected: AtomicU64,
+    rows_written: AtomicU64,
+    bytes_read: AtomicU64,
+    // (started, finished)
+    timing: Mutex<(Option<Instant>, Option<Instant>)>,
+}
+
+impl InputStats {
+    fn new(name: String) -> Self {
+        InputStats {
+            name,
+            rows_read: AtomicU64::new(0),
+            rows_rejected: AtomicU64::new(0),
+            rows_written: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            timing: Mutex::new((None, None)),
+        }
+    }
+
+    pub fn begin(&self) {
+        if let Ok(mut timing) = self.timing.lock() {
+            timing.0 = Some(Instant::now())
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Ok(mut timing) = self.timing.lock() {
+            timing.1 = Some(Instant::now())
+        }
+    }
+
+    pub fn read(&self, rows: u64) {
+        self.rows_read.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub fn reject(&self, rows: u64) {
+        self.rows_rejected.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub fn written(&self, rows: u64) {
+        self.rows_written.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.timing.lock().map(|t| *t) {
+            Ok((Some(start), Some(end))) => end.duration_since(start),
+            Ok((Some(start), None)) => start.elapsed(),
+            _ => Duration::default(),
+        }
+    }
+
+    fn summary(&self) -> InputSummary {
+        InputSummary {
+            input: self.name.clone(),
+            rows_read: self.rows_read.load(Ordering::Relaxed),
+            rows_rejected: self.rows_rejected.load(Ordering::Relaxed),
+            rows_written: self.rows_written.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            elapsed_secs: self.elapsed().as_secs_f64(),
+        }
+    }
+}
+
+// Counts the (raw, possibly compressed) bytes read from an input
+pub struct Counted<R> {
+    inner: R,
+    stats: Arc<InputStats>,
+}
+
+impl<R> Counted<R> {
+    pub fn new(inner: R, stats: Arc<InputStats>) -> Self {
+        Counted { inner, stats }
+    }
+}
+
+impl<R: ioRead> ioRead for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> ioResult<usize> {
+        let count = self.inner.read(buf)?;
+        self.stats
+            .bytes_read
+            .fetch_add(count as u64, Ordering::Relaxed);
+        Ok(count)
+    }
+}
+
+// Statistics for a whole run, one entry per input sent to the workers.
+// Inputs may be tracked while the run is being summarized elsewhere
+pub struct RunStats {
+    started: Instant,
+    inputs: Mutex<Vec<Arc<InputStats>>>,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        RunStats {
+            started: Instant::now(),
+            inputs: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Registers an input, returning its counters
+    pub fn track(&self, name: String) -> Arc<InputStats> {
+        let stats = Arc::new(InputStats::new(name));
+        if let Ok(mut inputs) = self.inputs.lock() {
+            inputs.push(Arc::clone(&stats))
+        }
+        stats
+    }
+
+    pub fn summary(&self) -> Summary {
+        let inputs = match self.inputs.lock() {
+            Ok(inputs) => inputs.iter().map(|i| i.summary()).collect(),
+            Err(_) => Vec::new(),
+        };
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        let sum = |field: fn(&InputSummary) -> u64| inputs.iter().map(field).sum::<u64>();
+        let (rows_read, rows_rejected, rows_written, bytes_read) = (
+            sum(|i| i.rows_read),
+            sum(|i| i.rows_rejected),
+            sum(|i| i.rows_written),
+            sum(|i| i.bytes_read),
+        );
+
+        Summary {
+            elapsed_secs,
+            rows_read,
+            rows_rejected,
+            rows_written,
+            bytes_read,
+            rows_per_sec: per_sec(rows_written, elapsed_secs),
+            bytes_per_sec: per_sec(bytes_read, elapsed_secs),
+            inputs,
+        }
+    }
+}
+
+fn per_sec(count: u64, secs: f64) -> f64 {
+    match secs > 0.0 {
+        true => count as f64 / secs,
+        false => 0.0,
+    }
+}
+
+// Serializable snapshot of a run's statistics
+#[derive(Serialize)]
+pub struct Summary {
+    pub elapsed_secs: f64,
+    pub rows_read: u64,
+    pub rows_rejected: u64,
+    pub rows_written: u64,
+    pub bytes_read: u64,
+    pub rows_per_sec: f64,
+    pub bytes_per_sec: f64,
+    inputs: Vec<InputSummary>,
+}
+
+#[derive(Serialize)]
+pub struct InputSummary {
+    input: String,
+    rows_read: u64,
+    rows_rejected: u64,
+    rows_written: u64,
+    bytes_read: u64,
+    elapsed_secs: f64,
+}
+
+impl Summary {
+    pub fn write_json(&self, path: &str) -> Result<(), ErrorKind> {
+        let unwritable =
+            |e: &dyn fmt::Display| ErrorKind::UnwritableOutput(format!("{}: {}", path, e));
+        let file = File::create(path).map_err(|e| unwritable(&e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|e| unwritable(&e))
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} input(s) in {:.2}s: {} rows read, {} rejected, {} written, {} ({:.0} rows/s, {}/s)",
+            self.inputs.len(),
+            self.elapsed_secs,
+            self.rows_read,
+            self.rows_rejected,
+            self.rows_written,
+            human_bytes(self.bytes_read as f64),
+            self.rows_per_sec,
+            human_bytes(self.bytes_per_sec)
+        )?;
+        for input in self.inputs.iter() {
+            write!(
+                f,
+                "\n  {}: {} rows read, {} rejected, {} written, {} in {:.2}s",
+                input.input,
+                input.rows_read,
+                input.rows_rejected,
+                input.rows_written,
+                human_bytes(input.bytes_read as f64),
+                input.elapsed_secs
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn human_bytes(bytes: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{:.0} {}", size, units[unit]),
+        _ => format!("{:.1} {}", size, units[unit]),
+    }
+}
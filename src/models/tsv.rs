@@ -0,0 +1,230 @@
+use {
+    crate::models::{assets::Row, error::ErrorKind, records::Lines},
+    csv::ByteRecord,
+    std::{
+        borrow::Cow,
+        io::{Read as ioRead, Write as ioWrite},
+        iter, mem,
+        sync::Arc,
+    },
+};
+
+// Splits TSV into records, one per line. Fields are never quoted, instead
+// tabs, line breaks and backslashes are escaped as \t, \n, \r and \\
+pub struct Tsv<R> {
+    lines: Lines<R>,
+}
+
+impl<R: ioRead> Tsv<R> {
+    // Reads the header line, if the input has one, returning the reader
+    // alongside the header names
+    pub fn new(source: R, header: bool) -> Result<(Self, Option<ByteRecord>), ErrorKind> {
+        let mut lines = Lines::new(source, false);
+        let headers = match header {
+            true => Some(lines.next_line()?.map(split).unwrap_or_default()),
+            false => None,
+        };
+
+        Ok((Tsv { lines }, headers))
+    }
+}
+
+impl<R: ioRead> Iterator for Tsv<R> {
+    type Item = Result<ByteRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.lines.next_line() {
+            Ok(Some(line)) => split(line),
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e.to_string())),
+        };
+        record.set_position(Some(self.lines.position()));
+
+        Some(Ok(record))
+    }
+}
+
+fn split(line: &[u8]) -> ByteRecord {
+    line.split(|&b| b == b'\t')
+        .map(|field| unescape(field))
+        .collect()
+}
+
+// Unknown escapes, and a trailing backslash, are kept as is
+fn unescape(field: &[u8]) -> Cow<'_, [u8]> {
+    if !field.contains(&b'\\') {
+        return Cow::Borrowed(field);
+    }
+
+    let mut unescaped = Vec::with_capacity(field.len());
+    let mut bytes = field.iter();
+    while let Some(&b) = bytes.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b't') => unescaped.push(b'\t'),
+            Some(b'n') => unescaped.push(b'\n'),
+            Some(b'r') => unescaped.push(b'\r'),
+            Some(b'\\') => unescaped.push(b'\\'),
+            Some(&other) => unescaped.extend_from_slice(&[b'\\', other]),
+            None => unescaped.push(b'\\'),
+        }
+    }
+
+    Cow::Owned(unescaped)
+}
+
+// Escapes fields into a line, without its line break
+pub fn encode<'a, I>(fields: I, line: &mut Vec<u8>)
+where
+    I: Iterator<Item = &'a str>,
+{
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            line.push(b'\t');
+        }
+        escape(field, line);
+    }
+}
+
+fn escape(field: &str, line: &mut Vec<u8>) {
+    for &b in field.as_bytes() {
+        match b {
+            b'\t' => line.extend_from_slice(b"\\t"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            b'\r' => line.extend_from_slice(b"\\r"),
+            b'\\' => line.extend_from_slice(b"\\\\"),
+            b => line.push(b),
+        }
+    }
+}
+
+// Writes rows as TSV, under a single header line taken from the first row.
+// When continuing an existing table, its header line is kept instead
+pub struct TsvWriter {
+    header: Option<Arc<Vec<String>>>,
+    continues: bool,
+    warned: bool,
+    line: Vec<u8>,
+    encoded: Vec<u8>,
+}
+
+impl TsvWriter {
+    pub fn new(continues: bool) -> Self {
+        TsvWriter {
+            header: None,
+            continues,
+            warned: false,
+            line: Vec::new(),
+            encoded: Vec::new(),
+        }
+    }
+
+    pub fn write<W: ioWrite>(&mut self, writer: &mut W, row: &Row) -> Result<(), ErrorKind> {
+        let mut encoded = mem::replace(&mut self.encoded, Vec::new());
+        encoded.clear();
+        encode(row.fields(), &mut encoded);
+        let res = self.write_encoded(writer, row.headers(), row.fields().count(), &encoded);
+        self.encoded = encoded;
+
+        res
+    }
+
+    // Writes a row already encoded by encode(), with its headers and number of fields
+    pub fn write_encoded<W: ioWrite>(
+        &mut self,
+        writer: &mut W,
+        headers: &Arc<Vec<String>>,
+        fields: usize,
+        encoded: &[u8],
+    ) -> Result<(), ErrorKind> {
+        match &self.header {
+            None => {
+                if !self.continues {
+                    self.write_line(writer, headers.iter().map(|h| h.as_str()))?;
+                }
+                self.header = Some(Arc::clone(headers));
+            }
+            Some(header) if !self.warned && !Arc::ptr_eq(header, headers) && header != headers => {
+                self.warned = true;
+                warn!("Headers differ from the TSV header line, fields are written by position...")
+            }
+            _ => (),
+        }
+
+        // Short rows are padded, so every row has a field for each header.
+        // A row without fields has no first field to follow
+        let width = self.header.as_ref().map_or(0, |h| h.len());
+        let padding = match fields {
+            0 => width.saturating_sub(1),
+            _ => width.saturating_sub(fields),
+        };
+        self.line.clear();
+        self.line.extend_from_slice(encoded);
+        self.line.extend(iter::repeat(b'\t').take(padding));
+        self.line.push(b'\n');
+        writer.write_all(&self.line)?;
+
+        Ok(())
+    }
+
+    fn write_line<'a, W, I>(&mut self, writer: &mut W, fields: I) -> Result<(), ErrorKind>
+    where
+        W: ioWrite,
+        I: Iterator<Item = &'a str>,
+    {
+        self.line.clear();
+        encode(fields, &mut self.line);
+        self.line.push(b'\n');
+        writer.write_all(&self.line)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_round_trip() {
+        // (field, escaped)
+        let cases = [
+            ("plain", "plain"),
+            ("", ""),
+            ("a\tb", "a\\tb"),
+            ("line\nbreak\r\n", "line\\nbreak\\r\\n"),
+            ("back\\slash", "back\\\\slash"),
+            ("trailing\\", "trailing\\\\"),
+            ("\\", "\\\\"),
+            ("\\t", "\\\\t"),
+            ("\t\\\n", "\\t\\\\\\n"),
+        ];
+
+        for (field, escaped) in cases.iter() {
+            let mut line = Vec::new();
+            escape(field, &mut line);
+            assert_eq!(String::from_utf8_lossy(&line), *escaped, "{:?}", field);
+            assert_eq!(&*unescape(&line), field.as_bytes(), "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn unescape_keeps_unknown() {
+        // (field as read, unescaped)
+        let cases = [
+            ("trailing\\", "trailing\\"),
+            ("\\", "\\"),
+            ("\\q", "\\q"),
+            ("a\\\\\\", "a\\\\"),
+            ("\\x41", "\\x41"),
+        ];
+
+        for (field, unescaped) in cases.iter() {
+            let field = field.as_bytes();
+            assert_eq!(&*unescape(field), unescaped.as_bytes(), "{:?}", field);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use {
+    crate::models::{error::ErrorKind, records::Lines},
+    csv::ByteRecord,
+    std::io::Read as ioRead,
+};
+
+// Splits column aligned text, such as the output of ps or df, into records
+// on runs of spaces and tabs. With max_split, a line is split at most that
+// many times, leaving the last field with the rest of the line
+pub struct Whitespace<R> {
+    lines: Lines<R>,
+    max_split: Option<usize>,
+}
+
+impl<R: ioRead> Whitespace<R> {
+    // Reads the header line, if the input has one, returning the reader
+    // alongside the header names
+    pub fn new(
+        source: R,
+        max_split: Option<usize>,
+        header: bool,
+    ) -> Result<(Self, Option<ByteRecord>), ErrorKind> {
+        let mut lines = Lines::new(source, true);
+        let headers = match header {
+            true => Some(
+                lines
+                    .next_line()?
+                    .map(|line| split(line, max_split))
+                    .unwrap_or_default(),
+            ),
+            false => None,
+        };
+
+        Ok((Whitespace { lines, max_split }, headers))
+    }
+}
+
+impl<R: ioRead> Iterator for Whitespace<R> {
+    type Item = Result<ByteRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.lines.next_line() {
+            Ok(Some(line)) => split(line, self.max_split),
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e.to_string())),
+        };
+        record.set_position(Some(self.lines.position()));
+
+        Some(Ok(record))
+    }
+}
+
+fn split(line: &[u8], max_split: Option<usize>) -> ByteRecord {
+    let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+    let mut record = ByteRecord::new();
+    let mut rest = line;
+
+    loop {
+        let start = rest.iter().position(|b| !is_space(b)).unwrap_or(rest.len());
+        rest = &rest[start..];
+        if rest.is_empty() {
+            break;
+        }
+
+        // The last field allowed keeps the rest of the line, spaces and all
+        if max_split.map_or(false, |max| record.len() >= max) {
+            record.push_field(rest);
+            break;
+        }
+
+        let end = rest.iter().position(is_space).unwrap_or(rest.len());
+        record.push_field(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_on_runs() {
+        // (line, max split, expected fields)
+        let cases: [(&str, Option<usize>, &[&str]); 12] = [
+            ("a b c", None, &["a", "b", "c"]),
+            ("  a   b\t\tc  ", None, &["a", "b", "c"]),
+            ("a \t b", None, &["a", "b"]),
+            ("", None, &[]),
+            (" \t ", None, &[]),
+            // The whole line, less leading spaces, is one field
+            ("  a b  ", Some(0), &["a b  "]),
+            ("a b c", Some(1), &["a", "b c"]),
+            // The last field keeps internal, repeated and trailing spaces
+            ("sda  /mnt/My  Disk ", Some(1), &["sda", "/mnt/My  Disk "]),
+            ("a\t\tb\tc\t", Some(1), &["a", "b\tc\t"]),
+            ("a b c", Some(2), &["a", "b", "c"]),
+            // Fewer fields than allowed
+            ("a b", Some(5), &["a", "b"]),
+            ("a b  ", Some(2), &["a", "b"]),
+        ];
+
+        for (line, max_split, expected) in cases.iter() {
+            let record = split(line.as_bytes(), *max_split);
+            let fields = record.iter().collect::<Vec<&[u8]>>();
+            let expected = expected.iter().map(|f| f.as_bytes()).collect::<Vec<_>>();
+            assert_eq!(fields, expected, "{:?} {:?}", line, max_split);
+        }
+    }
+}
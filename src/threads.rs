@@ -4,175 +4,342 @@ use {
         cli::ProgramArgs,
         match_with_log,
         models::{
-            assets::{Output, OutputFormat, Record},
+            assets::{AnnouncingSender, Output, OutputFormat, Record, Row},
             build_json, build_yaml,
+            encode::{write_encoded, Encoded},
             error::ErrorKind,
             get_writer, parse_csv_source,
+            sink::{ContinueArray, Destination, Resume, StripPrefix},
+            stats::InputStats,
+            tsv::TsvWriter,
         },
     },
     serde::{ser::SerializeSeq, Serializer},
+    serde_json::ser::Formatter as JsonFormatter,
     std::{
-        io::{BufWriter, Read as ioRead},
-        sync::mpsc::{sync_channel as syncQueue, Receiver, SyncSender},
+        collections::{BTreeMap, VecDeque},
+        io::{BufWriter, Read as ioRead, Write as ioWrite},
+        mem,
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{
+            mpsc::{
+                channel as asyncQueue, sync_channel as syncQueue, Receiver, Sender, SyncSender,
+                TrySendError,
+            },
+            Arc, Mutex,
+        },
         thread::{Builder as thBuilder, JoinHandle},
+        time::Duration,
     },
 };
 
+// How often the reader checks whether the builder is free for the next source,
+// while one is waiting to be handed over
+const HANDOVER_INTERVAL: Duration = Duration::from_millis(5);
+
 // Spawns workers and the channels which communicate input segments.
 // Each input source "from_source" is assigned a new channel, and said channel's
 // rx sent through the "meta channels." This implementation ensures that the control
 // flow mirrors the data flow
+// Records cross each channel in batches, with at most opts.channel_depth()
+// batches buffered per channel
+// The write destination is handed back through the threads' return values,
+// and should only be committed if every thread succeeded
 pub(crate) fn spawn_workers(
     opts: &'static ProgramArgs,
-    from_source: Receiver<Box<dyn ioRead + Send>>,
-) -> Result<JoinHandle<Result<(), ErrorKind>>, ErrorKind> {
+    from_source: Receiver<(Arc<InputStats>, Box<dyn ioRead + Send>)>,
+) -> Result<JoinHandle<Result<Destination, ErrorKind>>, ErrorKind> {
     // Meta channel: |Reader -> Builder|, delivers new receivers to builder
     let (ReBu_tx, ReBu_rx): (
-        SyncSender<Receiver<(Vec<String>, Record)>>,
-        Receiver<Receiver<(Vec<String>, Record)>>,
+        SyncSender<(Arc<InputStats>, Receiver<Vec<(Arc<Vec<String>>, Record)>>)>,
+        Receiver<(Arc<InputStats>, Receiver<Vec<(Arc<Vec<String>>, Record)>>)>,
     ) = syncQueue(0);
     // Meta channel: |Builder -> Writer|, delivers new receivers to writer
-    let (BuWr_tx, BuWr_rx): (SyncSender<Receiver<Output>>, Receiver<Receiver<Output>>) =
-        syncQueue(0);
+    let (BuWr_tx, BuWr_rx): (
+        SyncSender<(Arc<InputStats>, Receiver<Vec<Output>>)>,
+        Receiver<(Arc<InputStats>, Receiver<Vec<Output>>)>,
+    ) = syncQueue(0);
+
+    // Opened up front, so an unwritable destination fails before any input is read
+    let (destination, resume) = get_writer(opts)?;
 
     // Writer
-    let thWriter =
-        thBuilder::new()
-            .name(format!("Writer"))
-            .spawn(move || -> Result<(), ErrorKind> {
-                debug!("Writer initialized");
-                let rx_builder = BuWr_rx;
-                let opts = &opts;
-                let mut writer = BufWriter::new(get_writer(opts.writer()));
-                info!("Buffered writer initialized");
-
-                // Hot loop
-                while let Some(channel) = rx_builder.iter().next() {
-                    let _res: Result<(), ErrorKind> = match opts.output_type() {
-                        OutputFormat::Json => match_with_log!(
+    let thWriter = thBuilder::new().name(format!("Writer")).spawn(
+        move || -> Result<Destination, ErrorKind> {
+            debug!("Writer initialized");
+            let rx_builder = BuWr_rx;
+            let opts = &opts;
+            let mut writer = BufWriter::new(destination);
+            info!("Buffered writer initialized");
+
+            // Only the first channel continues appended to output
+            let mut resume = resume;
+            // TSV output has a single header line, across every channel
+            let mut tsv = TsvWriter::new(resume == Resume::TsvTable);
+
+            // Hot loop
+            while let Some((stats, channel)) = rx_builder.iter().next() {
+                let outputs = channel
+                    .iter()
+                    .inspect(|batch| stats.written(batch.len() as u64))
+                    .flatten();
+                let res: Result<(), ErrorKind> =
+                    match (opts.output_type(), mem::replace(&mut resume, Resume::Fresh)) {
+                        // Rows were serialized by the builder pool, and only need joining
+                        (format, resume) if opts.jobs() > 1 => match_with_log!(
+                            write_encoded(
+                                &mut writer,
+                                format,
+                                resume,
+                                outputs,
+                                &mut tsv,
+                                opts.follow()
+                            ),
+                            info!(
+                                "Using {} writer, joining rows encoded by the builder pool",
+                                format
+                            )
+                        ),
+                        (OutputFormat::Json, Resume::JsonArray { empty }) => match_with_log!(
+                            write_json(
+                                serde_json::Serializer::new(ContinueArray::new(
+                                    &mut writer,
+                                    !empty
+                                )),
+                                outputs
+                            ),
+                            info!("Using Json writer, continuing existing array")
+                        ),
+                        (OutputFormat::Json, _) => match_with_log!(
+                            write_json(serde_json::Serializer::new(&mut writer), outputs),
+                            info!("Using Json writer")
+                        ),
+                        (OutputFormat::JsonPretty, Resume::JsonArray { empty }) => match_with_log!(
+                            write_json(
+                                serde_json::Serializer::pretty(ContinueArray::new(
+                                    &mut writer,
+                                    !empty
+                                )),
+                                outputs
+                            ),
+                            info!("Using pretty Json writer, continuing existing array")
+                        ),
+                        (OutputFormat::JsonPretty, _) => match_with_log!(
+                            write_json(serde_json::Serializer::pretty(&mut writer), outputs),
+                            info!("Using pretty Json writer")
+                        ),
+                        (OutputFormat::NdJson, _) => match_with_log!(
                             {
-                                let mut ser = serde_json::Serializer::new(&mut writer);
-                                let mut seq =
-                                    ser.serialize_seq(None).map_err(|e| ErrorKind::from(e))?;
-                                for output in channel.iter() {
-                                    seq.serialize_element(&output)
+                                for output in outputs {
+                                    serde_json::to_writer(&mut writer, &output)
                                         .map_err(|e| ErrorKind::from(e))?;
+                                    writer.write_all(b"\n")?;
+                                    if opts.follow() {
+                                        writer.flush()?;
+                                    }
                                 }
-                                seq.end().map_err(|e| ErrorKind::from(e))?;
                                 Ok(())
                             },
-                            info!("Using Json writer")
+                            info!("Using newline delimited Json writer")
                         ),
-                        OutputFormat::JsonPretty => match_with_log!(
+                        (OutputFormat::Yaml, resume) => match_with_log!(
                             {
-                                let mut ser = serde_json::Serializer::pretty(&mut writer);
-                                let mut seq =
-                                    ser.serialize_seq(None).map_err(|e| ErrorKind::from(e))?;
-                                for output in channel.iter() {
-                                    seq.serialize_element(&output)
-                                        .map_err(|e| ErrorKind::from(e))?;
+                                let all_output: Vec<Output> = outputs.collect();
+                                match resume {
+                                    // Nothing to add, and an empty sequence cannot extend the existing one
+                                    Resume::YamlSequence { empty: false }
+                                        if all_output.is_empty() =>
+                                    {
+                                        ()
+                                    }
+                                    // Puts back the dropped empty sequence
+                                    Resume::YamlSequence { empty: true }
+                                        if all_output.is_empty() =>
+                                    {
+                                        writer.write_all(b"[]\n")?
+                                    }
+                                    // Continuing the existing sequence, so no new document
+                                    Resume::YamlSequence { .. } => serde_yaml::to_writer(
+                                        StripPrefix::new(&mut writer, b"---\n"),
+                                        &all_output,
+                                    )
+                                    .map_err(|e| ErrorKind::from(e))?,
+                                    _ => serde_yaml::to_writer(&mut writer, &all_output)
+                                        .map_err(|e| ErrorKind::from(e))?,
                                 }
-                                seq.end().map_err(|e| ErrorKind::from(e))?;
+
                                 Ok(())
                             },
-                            info!("Using pretty Json writer")
+                            info!("Using Yaml writer")
                         ),
-                        OutputFormat::Yaml => match_with_log!(
+                        (OutputFormat::Tsv, _) => match_with_log!(
                             {
-                                let all_output: Vec<Output> = channel.iter().collect();
-                                serde_yaml::to_writer(&mut writer, &all_output)
-                                    .map_err(|e| ErrorKind::from(e))?;
-
+                                for output in outputs {
+                                    match output {
+                                        Output::Row(row) => tsv.write(&mut writer, &row)?,
+                                        _ => unreachable!(),
+                                    }
+                                    if opts.follow() {
+                                        writer.flush()?;
+                                    }
+                                }
                                 Ok(())
                             },
-                            info!("Using Yaml writer")
+                            info!("Using TSV writer")
                         ),
                     };
-                }
+                res?;
+                stats.finish();
+            }
 
-                // Cleanup
-                debug!("Writer closing");
-                Ok(())
-            })?;
+            // Cleanup
+            let mut destination = writer
+                .into_inner()
+                .map_err(|e| ErrorKind::from(e.into_error()))?
+                .finish()?;
+            destination.flush()?;
+            debug!("Writer closing");
+            Ok(destination)
+        },
+    )?;
+
+    // Builder pool, only used if more than one job is requested
+    let (tx_pool, pool) = match opts.jobs() {
+        1 => (None, Vec::new()),
+        jobs => {
+            let (tx_pool, rx_pool): (SyncSender<BuildJob>, Receiver<BuildJob>) = syncQueue(jobs);
+            let rx_pool = Arc::new(Mutex::new(rx_pool));
+            let pool = (0..jobs)
+                .map(|i| {
+                    let rx_pool = Arc::clone(&rx_pool);
+                    thBuilder::new()
+                        .name(format!("Builder-{}", i))
+                        .spawn(move || build_worker(opts, rx_pool))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            info!("Builder pool of {} workers initialized", jobs);
+            (Some(tx_pool), pool)
+        }
+    };
 
     // Builder
-    let thBuilder =
-        thBuilder::new()
-            .name(format!("Builder"))
-            .spawn(move || -> Result<(), ErrorKind> {
-                debug!("Builder initialized");
-                let tx_writer = BuWr_tx;
-                let rx_reader = ReBu_rx;
-                let opts = &opts;
-
-                // Hot loop
-                while let Some(channel) = rx_reader.iter().next() {
-                    let (data_tx, data_rx): (SyncSender<Output>, Receiver<Output>) = syncQueue(10);
-                    tx_writer.send(data_rx).map_err(|_| {
-                        ErrorKind::UnexpectedChannelClose(format!(
-                            "failed to send next |builder -> writer| channel, writer has hung up"
-                        ))
-                    })?;
-                    let res = channel
-                        .iter()
-                        .map(|(header, record)| match opts.output_type() {
-                            OutputFormat::Json => Output::Json(build_json(header, record)),
-                            OutputFormat::JsonPretty => Output::Json(build_json(header, record)),
-                            OutputFormat::Yaml => Output::Yaml(build_yaml(header, record)),
-                        });
-                    for item in res {
-                        data_tx.send(item).map_err(|_| {
-                            ErrorKind::UnexpectedChannelClose(format!(
-                                "writer in |builder -> writer| channel has hung up"
-                            ))
-                        })?;
+    let thBuilder = thBuilder::new().name(format!("Builder")).spawn(
+        move || -> Result<Destination, ErrorKind> {
+            debug!("Builder initialized");
+            let tx_writer = BuWr_tx;
+            let rx_reader = ReBu_rx;
+            let opts = &opts;
+
+            // Hot loop
+            while let Some((stats, channel)) = rx_reader.iter().next() {
+                let (data_tx, data_rx): (SyncSender<Vec<Output>>, Receiver<Vec<Output>>) =
+                    syncQueue(opts.channel_depth());
+                tx_writer.send((stats, data_rx)).map_err(|_| {
+                    ErrorKind::UnexpectedChannelClose(format!(
+                        "failed to send next |builder -> writer| channel, writer has hung up"
+                    ))
+                })?;
+                match &tx_pool {
+                    // Build records in place
+                    None => {
+                        let res = channel.iter().map(|batch| build_batch(opts, batch));
+                        for built in res {
+                            data_tx.send(built).map_err(|_| {
+                                ErrorKind::UnexpectedChannelClose(format!(
+                                    "writer in |builder -> writer| channel has hung up"
+                                ))
+                            })?;
+                        }
+                    }
+                    // Fan batches of records out to the pool, with a sequencer
+                    // putting them back in order for the writer
+                    Some(tx_pool) => {
+                        let (result_tx, result_rx): (SyncSender<BuiltBatch>, Receiver<BuiltBatch>) =
+                            syncQueue(opts.jobs());
+                        // Bounds the number of batches in flight, and thus how many
+                        // the sequencer may need to hold while waiting for a slow one
+                        let (credit_tx, credit_rx): (SyncSender<()>, Receiver<()>) =
+                            syncQueue(opts.jobs() * 2);
+                        let thSequencer = thBuilder::new()
+                            .name(format!("Sequencer"))
+                            .spawn(move || sequence_batches(result_rx, credit_rx, data_tx))?;
+
+                        for (index, batch) in channel.iter().enumerate() {
+                            // The sequencer only hangs up on failing, which
+                            // is returned on joining it
+                            if credit_tx.send(()).is_err() {
+                                break;
+                            }
+                            tx_pool
+                                .send((index, batch, result_tx.clone()))
+                                .map_err(|_| {
+                                    ErrorKind::UnexpectedChannelClose(format!(
+                                        "pool in |builder -> pool| channel has hung up"
+                                    ))
+                                })?;
+                        }
+
+                        // The sequencer finishes once every job's result sender is dropped
+                        drop(result_tx);
+                        drop(credit_tx);
+                        thSequencer
+                            .join()
+                            .map_err(|_| ErrorKind::ThreadFailed(format!("Sequencer")))??;
                     }
                 }
+            }
 
-                // Cleanup
-                drop(tx_writer);
-                thWriter.join().map_err(|_| {
+            // Cleanup
+            drop(tx_pool);
+            for worker in pool {
+                worker.join().map_err(|_| {
                     ErrorKind::ThreadFailed(format!(
                         "{}",
                         std::thread::current().name().unwrap_or("unnamed")
                     ))
                 })??;
-                debug!("Builder closing");
-                Ok(())
-            });
+            }
+            drop(tx_writer);
+            let destination = thWriter.join().map_err(|_| {
+                ErrorKind::ThreadFailed(format!(
+                    "{}",
+                    std::thread::current().name().unwrap_or("unnamed")
+                ))
+            })??;
+            debug!("Builder closing");
+            Ok(destination)
+        },
+    );
 
     // Reader
-    let thReader: JoinHandle<Result<(), ErrorKind>> = thBuilder::new()
+    let thReader: JoinHandle<Result<Destination, ErrorKind>> = thBuilder::new()
         .name(format!("Reader"))
-        .spawn(move || -> Result<(), ErrorKind> {
+        .spawn(move || -> Result<Destination, ErrorKind> {
             debug!("Reader initialized");
             let tx_builder = ReBu_tx;
             let opts = &opts;
 
             // Hot loop
-            while let Some(src) = from_source.iter().next() {
-                let (data_tx, data_rx): (
-                    SyncSender<(Vec<String>, Record)>,
-                    Receiver<(Vec<String>, Record)>,
-                ) = syncQueue(10);
-                tx_builder.send(data_rx).map_err(|_| {
-                    ErrorKind::UnexpectedChannelClose(format!(
-                        "failed to send next |reader -> builder| channel, builder has hung up"
-                    ))
-                })?;
-                parse_csv_source(&opts, src, data_tx)?;
-            }
+            let read = match opts.input_jobs() {
+                1 => read_sequentially(opts, from_source, &tx_builder),
+                _ => read_concurrently(opts, from_source, &tx_builder),
+            };
 
             // Cleanup
             drop(tx_builder);
-            thBuilder?.join().map_err(|_| {
+            let built = thBuilder?.join().map_err(|_| {
                 ErrorKind::ThreadFailed(format!(
                     "{}",
                     std::thread::current().name().unwrap_or("unnamed")
                 ))
-            })??;
+            })?;
+            // A builder which failed hangs up on the reader, so its error comes first
+            let destination = match (built, read) {
+                (Err(e), _) | (Ok(_), Err(e)) => return Err(e),
+                (Ok(destination), Ok(())) => destination,
+            };
             debug!("Reader closing");
-            Ok(())
+            Ok(destination)
         })
         .map_err(|_| {
             ErrorKind::ThreadFailed(format!(
@@ -183,3 +350,314 @@ pub(crate) fn spawn_workers(
 
     Ok(thReader)
 }
+
+// Parses one source at a time, handing each source's channel to the builder
+fn read_sequentially(
+    opts: &'static ProgramArgs,
+    from_source: Receiver<(Arc<InputStats>, Box<dyn ioRead + Send>)>,
+    tx_builder: &SyncSender<(Arc<InputStats>, Receiver<Vec<(Arc<Vec<String>>, Record)>>)>,
+) -> Result<(), ErrorKind> {
+    while let Some((stats, src)) = from_source.iter().next() {
+        let (data_tx, data_rx): (
+            SyncSender<Vec<(Arc<Vec<String>>, Record)>>,
+            Receiver<Vec<(Arc<Vec<String>>, Record)>>,
+        ) = syncQueue(opts.channel_depth());
+        tx_builder
+            .send((Arc::clone(&stats), data_rx))
+            .map_err(|_| {
+                ErrorKind::UnexpectedChannelClose(format!(
+                    "failed to send next |reader -> builder| channel, builder has hung up"
+                ))
+            })?;
+        parse_csv_source(
+            opts,
+            src,
+            &stats,
+            AnnouncingSender::<_, ()>::new(data_tx, None),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Notifications from concurrent source parsers to the reader
+enum SourceEvent {
+    // Source's channel is full or finished, and is worth handing to the builder
+    Ready(usize),
+    // Source's parser has exited
+    Done(Result<(), ErrorKind>),
+}
+
+// Parses up to opts.input_jobs() sources at a time, each in its own thread.
+// Source channels are handed to the builder in input order, or if unordered,
+// in the order they become ready. Channels are queued until the builder is
+// free, so parsers keep being started and finished meanwhile
+fn read_concurrently(
+    opts: &'static ProgramArgs,
+    from_source: Receiver<(Arc<InputStats>, Box<dyn ioRead + Send>)>,
+    tx_builder: &SyncSender<(Arc<InputStats>, Receiver<Vec<(Arc<Vec<String>>, Record)>>)>,
+) -> Result<(), ErrorKind> {
+    let (event_tx, event_rx): (Sender<SourceEvent>, Receiver<SourceEvent>) = asyncQueue();
+    let mut sources = from_source.iter().enumerate();
+    let mut exhausted = false;
+    let mut active = 0usize;
+    // Unordered only, channels waiting on their source to become ready
+    let mut waiting: BTreeMap<usize, (Arc<InputStats>, Receiver<Vec<(Arc<Vec<String>>, Record)>>)> =
+        BTreeMap::new();
+    // Channels to hand to the builder, in order
+    let mut pending: VecDeque<(Arc<InputStats>, Receiver<Vec<(Arc<Vec<String>>, Record)>>)> =
+        VecDeque::new();
+
+    loop {
+        // Fill any free slots
+        while !exhausted && active < opts.input_jobs() {
+            let (index, (stats, src)) = match sources.next() {
+                Some(next) => next,
+                None => {
+                    exhausted = true;
+                    break;
+                }
+            };
+            let (data_tx, data_rx): (
+                SyncSender<Vec<(Arc<Vec<String>>, Record)>>,
+                Receiver<Vec<(Arc<Vec<String>>, Record)>>,
+            ) = syncQueue(opts.channel_depth());
+            let announce = match opts.unordered() {
+                true => Some((event_tx.clone(), SourceEvent::Ready(index))),
+                false => None,
+            };
+            let events = event_tx.clone();
+            let source_stats = Arc::clone(&stats);
+            thBuilder::new()
+                .name(format!("Reader-{}", index))
+                .spawn(move || {
+                    let res = catch_unwind(AssertUnwindSafe(|| {
+                        parse_csv_source(
+                            opts,
+                            src,
+                            &source_stats,
+                            AnnouncingSender::new(data_tx, announce),
+                        )
+                    }))
+                    .unwrap_or_else(|_| Err(ErrorKind::ThreadFailed(format!("Reader-{}", index))));
+                    events.send(SourceEvent::Done(res)).ok();
+                })?;
+            active += 1;
+
+            match opts.unordered() {
+                true => drop(waiting.insert(index, (stats, data_rx))),
+                false => pending.push_back((stats, data_rx)),
+            }
+        }
+
+        // Hand over as many channels as the builder will take
+        while let Some(channel) = pending.pop_front() {
+            match tx_builder.try_send(channel) {
+                Ok(()) => (),
+                Err(TrySendError::Full(channel)) => {
+                    pending.push_front(channel);
+                    break;
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(ErrorKind::UnexpectedChannelClose(format!(
+                        "failed to send next |reader -> builder| channel, builder has hung up"
+                    )))
+                }
+            }
+        }
+
+        if exhausted && active == 0 && pending.is_empty() {
+            break;
+        }
+
+        // Our own event_tx is held, so this can only time out
+        let event = match pending.is_empty() {
+            true => event_rx.recv().ok(),
+            false => event_rx.recv_timeout(HANDOVER_INTERVAL).ok(),
+        };
+        match event {
+            Some(SourceEvent::Ready(index)) => {
+                if let Some(channel) = waiting.remove(&index) {
+                    debug!("Source {} is ready", index);
+                    pending.push_back(channel)
+                }
+            }
+            Some(SourceEvent::Done(res)) => {
+                active -= 1;
+                res?
+            }
+            None => (),
+        }
+    }
+
+    Ok(())
+}
+
+// A batch of records for a builder pool worker, tagged with its position
+// in the source and where to send the built output
+type BuildJob = (
+    usize,
+    Vec<(Arc<Vec<String>>, Record)>,
+    SyncSender<BuiltBatch>,
+);
+
+// A batch built by a builder pool worker, or why it could not be
+type BuiltBatch = (usize, Result<Vec<Output>, ErrorKind>);
+
+// Converts a record into the in-memory representation of the output format
+fn build_output(opts: &ProgramArgs, header: Arc<Vec<String>>, record: Record) -> Output {
+    match (opts.materialize(), opts.output_type()) {
+        (false, _) => Output::Row(Row::new(header, record)),
+        (true, OutputFormat::Json) => Output::Json(build_json(&header, record)),
+        (true, OutputFormat::JsonPretty) => Output::Json(build_json(&header, record)),
+        (true, OutputFormat::NdJson) => Output::Json(build_json(&header, record)),
+        (true, OutputFormat::Yaml) => Output::Yaml(build_yaml(&header, record)),
+        // There is no value tree for TSV
+        (true, OutputFormat::Tsv) => Output::Row(Row::new(header, record)),
+    }
+}
+
+// Converts a batch of records, keeping their order
+fn build_batch(opts: &ProgramArgs, batch: Vec<(Arc<Vec<String>>, Record)>) -> Vec<Output> {
+    batch
+        .into_iter()
+        .map(|(header, record)| build_output(opts, header, record))
+        .collect()
+}
+
+// Converts a batch of records, serializing each into the output format
+fn encode_batch(
+    opts: &ProgramArgs,
+    batch: Vec<(Arc<Vec<String>>, Record)>,
+) -> Result<Vec<Output>, ErrorKind> {
+    batch
+        .into_iter()
+        .map(|(header, record)| {
+            let output = build_output(opts, header, record);
+            Encoded::new(opts.output_type(), &output).map(Output::Encoded)
+        })
+        .collect()
+}
+
+// Builder pool worker, encodes batches until the pool's channel is closed
+fn build_worker(
+    opts: &'static ProgramArgs,
+    rx_pool: Arc<Mutex<Receiver<BuildJob>>>,
+) -> Result<(), ErrorKind> {
+    debug!("Builder worker initialized");
+    loop {
+        // The lock is only held while waiting for the next job
+        let job = rx_pool
+            .lock()
+            .map_err(|_| {
+                ErrorKind::ThreadFailed(format!(
+                    "{}",
+                    std::thread::current().name().unwrap_or("unnamed")
+                ))
+            })?
+            .recv();
+        let (index, batch, tx_sequencer) = match job {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+
+        // Failures are passed on, the sequencer aborts the source. A sequencer
+        // which has already aborted has returned its error, so this one is dropped
+        let built = encode_batch(opts, batch);
+        tx_sequencer.send((index, built)).ok();
+    }
+
+    debug!("Builder worker closing");
+    Ok(())
+}
+
+// Receives built batches in any order, forwarding them to the writer in order.
+// Stops at the first batch which failed to build, returning its error
+fn sequence_batches(
+    rx_pool: Receiver<BuiltBatch>,
+    credits: Receiver<()>,
+    tx_writer: SyncSender<Vec<Output>>,
+) -> Result<(), ErrorKind> {
+    let mut next = 0usize;
+    let mut pending: BTreeMap<usize, Vec<Output>> = BTreeMap::new();
+
+    for (index, built) in rx_pool.iter() {
+        pending.insert(index, built?);
+        while let Some(built) = pending.remove(&next) {
+            tx_writer.send(built).map_err(|_| {
+                ErrorKind::UnexpectedChannelClose(format!(
+                    "writer in |sequencer -> writer| channel has hung up"
+                ))
+            })?;
+            // Frees space for another batch to be sent to the pool
+            credits.recv().ok();
+            next += 1;
+        }
+    }
+
+    Ok(())
+}
+
+// Serializes a channel's output as a Json array
+fn write_json<W, F, I>(mut ser: serde_json::Serializer<W, F>, outputs: I) -> Result<(), ErrorKind>
+where
+    W: ioWrite,
+    F: JsonFormatter,
+    I: Iterator<Item = Output>,
+{
+    let mut seq = ser.serialize_seq(None).map_err(|e| ErrorKind::from(e))?;
+    for output in outputs {
+        seq.serialize_element(&output)
+            .map_err(|e| ErrorKind::from(e))?;
+    }
+    seq.end().map_err(|e| ErrorKind::from(e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    #[test]
+    fn sequencer_orders_and_aborts() {
+        // (order batches arrive in, which failed to build, batches written)
+        let cases: [(&[usize], Option<usize>, &[u64]); 6] = [
+            (&[0, 1, 2, 3], None, &[0, 1, 2, 3]),
+            (&[3, 1, 0, 2], None, &[0, 1, 2, 3]),
+            (&[1, 0, 3, 2], None, &[0, 1, 2, 3]),
+            (&[1, 0, 2, 3], Some(2), &[0, 1]),
+            (&[1, 2, 3, 0], Some(0), &[]),
+            // Aborts as soon as the failure arrives, without waiting on earlier batches
+            (&[0, 2, 1, 3], Some(2), &[0]),
+        ];
+
+        for (arrivals, failed, expected) in cases.iter() {
+            let (result_tx, result_rx) = syncQueue(arrivals.len());
+            let (credit_tx, credit_rx) = syncQueue(arrivals.len());
+            let (data_tx, data_rx) = syncQueue(arrivals.len());
+            for &index in arrivals.iter() {
+                let built = match *failed == Some(index) {
+                    true => Err(ErrorKind::ThreadFailed(format!("Builder-{}", index))),
+                    false => Ok(vec![Output::Json(json!(index))]),
+                };
+                credit_tx.send(()).unwrap();
+                result_tx.send((index, built)).unwrap();
+            }
+            drop(result_tx);
+
+            let res = sequence_batches(result_rx, credit_rx, data_tx);
+            let written = data_rx
+                .iter()
+                .flatten()
+                .map(|output| match output {
+                    Output::Json(index) => index.as_u64().unwrap(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<u64>>();
+
+            assert_eq!(res.is_err(), failed.is_some(), "{:?}", arrivals);
+            assert_eq!(written, *expected, "{:?}", arrivals);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// The builder pool (--jobs) and concurrent readers (--input-jobs) build batches
+// out of order, which must be put back in order before they are written
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+const ROWS: usize = 5000;
+
+// Writes CSV whose rows vary in size, so that batches take varying time to build
+fn generate_csv(path: &Path, offset: usize) {
+    let mut csv = String::from("id,value\n");
+    for row in offset..offset + ROWS {
+        csv.push_str(&format!("{},{}\n", row, "x".repeat(row % 97 * 13)));
+    }
+    fs::write(path, csv).unwrap();
+}
+
+fn convert(args: &[&str]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_aureate"))
+        .arg("-q")
+        .args(args)
+        .stderr(Stdio::inherit())
+        .output()
+        .expect("failed to run aureate");
+    assert!(
+        output.status.success(),
+        "{:?} exited with {}",
+        args,
+        output.status
+    );
+
+    output.stdout
+}
+
+#[test]
+fn jobs_keep_output_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.csv");
+    let second = dir.path().join("second.csv");
+    generate_csv(&first, 0);
+    generate_csv(&second, ROWS);
+    let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+
+    for format in &["json", "prettyj", "ndjson", "yaml", "tsv"] {
+        let expected = convert(&["-f", format, "-i", first, "-i", second]);
+
+        // (builder jobs, input jobs)
+        for (jobs, input_jobs) in &[("4", "1"), ("3", "2"), ("1", "2")] {
+            let output = convert(&[
+                "-f",
+                format,
+                "--jobs",
+                jobs,
+                "--input-jobs",
+                input_jobs,
+                "--batch-size",
+                "7",
+                "-i",
+                first,
+                "-i",
+                second,
+            ]);
+            assert!(
+                output == expected,
+                "{} output differs with --jobs {} --input-jobs {}",
+                format,
+                jobs,
+                input_jobs
+            );
+        }
+    }
+
+    // And is in input order to begin with
+    let ndjson = String::from_utf8(convert(&["-f", "ndjson", "-i", first, "-i", second])).unwrap();
+    let ids = ndjson
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].clone())
+        .map(|id| id.as_str().unwrap().parse::<usize>().unwrap())
+        .collect::<Vec<usize>>();
+    assert_eq!(ids, (0..ROWS * 2).collect::<Vec<usize>>());
+}